@@ -0,0 +1,150 @@
+//! request and response bodies of the ota backend, one pair per `EventType`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::carside::PushType;
+
+/// body of `LoginRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+    pub organization_id: i32,
+}
+
+/// reply of `LoginRequest`, the token also works for `TokenLoginRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginResponse {
+    pub id: i32,
+    pub token: String,
+    /// seconds the token is valid, if the server says.
+    #[serde(default)]
+    pub expires_in: Option<i64>,
+    /// organizations of the user, if the server lists them.
+    #[serde(default)]
+    pub organizations: Vec<Organization>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Organization {
+    pub id: i32,
+    pub name: String,
+}
+
+impl std::fmt::Display for Organization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+/// body of `TokenLoginRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenLoginRequest {
+    pub token: String,
+}
+
+/// reply of `TokenLoginRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenLoginResponse {
+    pub id: i32,
+}
+
+/// body of `OtaFetchVehicleTemplateTable`, empty filters match all vehicles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleTableRequest {
+    pub user_id: i32,
+    pub station_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Vehicle {
+    pub id: i32,
+    pub name: String,
+}
+
+/// reply of `OtaFetchVehicleTemplateTable`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleTable {
+    pub total: i32,
+    pub list: Vec<Vehicle>,
+}
+
+/// body of `OtaFetchVehicleTemplateItemContents`, the reply is the yaml text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateRequest {
+    pub vehicle_id: i32,
+}
+
+/// body of `OtaAddVehicleTemplateItem`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveTemplateRequest {
+    pub user_id: i32,
+    pub vehicle_id: i32,
+    pub old_config: String,
+    pub new_config: String,
+}
+
+/// reply of `OtaAddVehicleTemplateItem`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveTemplateResponse {
+    pub ok: bool,
+    pub message: Option<String>,
+}
+
+/// body of `OtaAddConfigurePublish`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishRequest {
+    pub modify_user_id: i32,
+    pub vehicle_id: i32,
+    pub bucket_name: String,
+    pub key: String,
+    pub name: String,
+    pub for_test: i32,
+}
+
+/// reply of `OtaAddConfigurePublish`, `id` names the published config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishResponse {
+    pub id: i32,
+    pub vehicle_id: i32,
+}
+
+/// body of `OtaEditConfigurePublish`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApproveRequest {
+    pub id: i32,
+    pub is_approve: bool,
+    pub approver_id: i32,
+}
+
+/// body of `OtaConfigurePublish`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushRequest {
+    pub vehicle_id: i32,
+    pub modify_user_id: i32,
+    pub vehicle_zelos_configure_build_history_id: i32,
+    pub command_type: PushType,
+}
+
+/// reply of `OtaEditConfigurePublish` and `OtaConfigurePublish`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkResponse {
+    pub ok: bool,
+}
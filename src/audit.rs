@@ -0,0 +1,133 @@
+//! append-only log of who changed, published and pushed what.
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::carside::PushType;
+use crate::cli::AuditArgs;
+use crate::local;
+use crate::mode::Change;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Event {
+    #[serde(rename_all = "camelCase")]
+    Save {
+        changes: Vec<Change>,
+        ok: bool,
+        message: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Publish { config_id: i32 },
+    #[serde(rename_all = "camelCase")]
+    Approve { config_id: i32, ok: bool },
+    #[serde(rename_all = "camelCase")]
+    Push {
+        config_id: i32,
+        push_type: PushType,
+        ok: bool,
+    },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = |ok: &bool| if *ok { "ok" } else { "failed" };
+        match self {
+            Event::Save {
+                changes,
+                ok,
+                message,
+            } => {
+                let changes: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+                write!(f, "save {} [{}]", result(ok), changes.join(", "))?;
+                if let Some(message) = message {
+                    write!(f, " {}", message)?;
+                }
+                Ok(())
+            }
+            Event::Publish { config_id } => write!(f, "publish {}", config_id),
+            Event::Approve { config_id, ok } => {
+                write!(f, "approve {} {}", config_id, result(ok))
+            }
+            Event::Push {
+                config_id,
+                push_type,
+                ok,
+            } => write!(f, "push {} {} {}", config_id, push_type, result(ok)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub at: chrono::DateTime<chrono::Local>,
+    pub user_id: i32,
+    pub host: String,
+    pub vehicle_id: i32,
+    pub vehicle_name: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+fn path() -> Result<PathBuf> {
+    Ok(local::cache_dir()?.join("audit.jsonl"))
+}
+
+pub fn append(record: &Record) -> Result<()> {
+    let path = path()?;
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// print the records matching all given filters, oldest first.
+pub fn query(args: &AuditArgs) -> Result<()> {
+    let path = path()?;
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(anyhow::anyhow!("read {:?} failed: {}", path, e)),
+    };
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                tracing::warn!("skip broken audit line: {}", e);
+                continue;
+            }
+        };
+        let day = record.at.date_naive();
+        if args
+            .vehicle
+            .as_ref()
+            .is_some_and(|v| *v != record.vehicle_name)
+            || args.user.is_some_and(|u| u != record.user_id)
+            || args.since.is_some_and(|since| day < since)
+            || args.until.is_some_and(|until| day > until)
+        {
+            continue;
+        }
+        if args.json {
+            println!("{}", line);
+        } else {
+            println!(
+                "{}\tuser {}\t{}\t{}\t{}",
+                record.at.format("%Y-%m-%d %H:%M:%S"),
+                record.user_id,
+                record.host,
+                record.vehicle_name,
+                record.event
+            );
+        }
+    }
+    Ok(())
+}
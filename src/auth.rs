@@ -1,12 +1,26 @@
-use crate::connection::EventType;
-use crate::Ota;
+use std::path::{Path, PathBuf};
+
+use crate::api::{LoginResponse, Organization};
+use crate::cli::{self, LoginArgs};
+use crate::client::DEFAULT_ORGANIZATION_ID;
+use crate::error::OtaError;
+use crate::OtaClient;
 use anyhow::Result;
+use chrono::TimeZone;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct UserToken {
+    /// the server that issued it, a token is never sent elsewhere.
+    endpoint: String,
+    /// the organization it was issued for.
+    organization_id: i32,
+    /// the user it was issued to, empty in caches written before it was kept.
+    #[serde(default)]
+    username: String,
     token: String,
-    expires_at: chrono::DateTime<chrono::Local>,
+    /// `None` if neither the server nor the token say, it is used until rejected.
+    expires_at: Option<chrono::DateTime<chrono::Local>>,
 }
 fn get_username() -> Result<String> {
     inquire::Text::new("username")
@@ -20,80 +34,175 @@ fn get_password() -> Result<String> {
         .prompt()
         .map_err(|e| anyhow::anyhow!(e))
 }
+fn get_organization(organizations: Vec<Organization>) -> Result<Organization> {
+    inquire::Select::new("organization", organizations)
+        .prompt()
+        .map_err(|e| anyhow::anyhow!(e))
+}
+/// `None` if the user gives up.
+fn get_organization_id(refused: &str) -> Result<Option<i32>> {
+    inquire::CustomType::<i32>::new("organization id")
+        .with_help_message(refused)
+        .with_error_message("not an organization id")
+        .prompt_skippable()
+        .map_err(|e| anyhow::anyhow!(e))
+}
 
-async fn auth_with_password(ota: &Ota, username: &str, password: &str) -> Result<(i32, String)> {
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct AuthReqBody<'a> {
-        username: &'a str,
-        password: &'a str,
-        organization_id: i32,
+/// the `exp` claim of a jwt, unverified, only to know when to log in again.
+fn jwt_expiry(token: &str) -> Option<chrono::DateTime<chrono::Local>> {
+    #[derive(Deserialize)]
+    struct Claims {
+        exp: i64,
     }
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct AuthRespBody {
-        id: i32,
-        token: String,
-    }
-    let req = AuthReqBody {
-        username,
-        password,
-        organization_id: 1,
-    };
-    let resp: AuthRespBody = ota
-        .conn
-        .request(EventType::LoginRequest, "", &req)
-        .await
-        .map_err(|e| {
-            tracing::error!("auth failed, please check username and password");
-            anyhow::anyhow!("auth error: {:?}", e)
-        })?;
-    Ok((resp.id, resp.token))
+    let mut parts = token.split('.');
+    let (_, payload, _) = (parts.next()?, parts.next()?, parts.next()?);
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+    let at = chrono::NaiveDateTime::from_timestamp_opt(claims.exp, 0)?;
+    Some(chrono::Local.from_utc_datetime(&at))
 }
 
-async fn auth_with_token(ota: &Ota, token: &str) -> Result<i32> {
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct AuthReqBody<'a> {
-        token: &'a str,
+fn expires_at(login: &LoginResponse) -> Option<chrono::DateTime<chrono::Local>> {
+    match login.expires_in {
+        Some(secs) => Some(chrono::Local::now() + chrono::Duration::seconds(secs)),
+        None => jwt_expiry(&login.token),
     }
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct AuthRespBody {
-        id: i32,
+}
+
+/// one cached token per profile and endpoint.
+fn token_path(profile: Option<&str>, endpoint: &str) -> Result<PathBuf> {
+    let profile: String = profile
+        .unwrap_or("default")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = format!("{}-{}.json", profile, &crate::local::hash(endpoint)[..12]);
+    Ok(crate::local::cache_dir()?.join("tokens").join(name))
+}
+
+fn save_token(
+    path: &Path,
+    endpoint: &str,
+    username: &str,
+    organization_id: i32,
+    login: &LoginResponse,
+) {
+    let user_token = UserToken {
+        endpoint: endpoint.to_string(),
+        organization_id,
+        username: username.to_string(),
+        token: login.token.clone(),
+        expires_at: expires_at(login),
+    };
+    let result = std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| std::fs::write(path, serde_json::to_string(&user_token)?));
+    if let Err(e) = result {
+        tracing::warn!("cache token to {:?} failed: {}", path, e);
     }
-    let req = AuthReqBody { token };
-    let resp: AuthRespBody = ota
-        .conn
-        .request(EventType::TokenLoginRequest, "", &req)
-        .await?;
-    Ok(resp.id)
 }
 
-pub async fn auth(ota: &Ota) -> Result<i32> {
+/// the cached token, a broken cache is ignored.
+fn read_token(path: &Path) -> Option<UserToken> {
+    let text = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text)
+        .inspect_err(|e| tracing::warn!("ignore broken token cache: {}", e))
+        .ok()
+}
+
+pub async fn auth(client: &mut OtaClient, args: &LoginArgs, profile: Option<&str>) -> Result<i32> {
     tracing::info!("start auth... ");
 
-    let user_dir = directories::UserDirs::new().ok_or(anyhow::anyhow!("can't find home dir"))?;
-    let token_file = user_dir.home_dir().join(".cache/ota-yaml/token");
-    if tokio::fs::canonicalize(&token_file).await.is_ok() {
-        let user_token: UserToken =
-            serde_json::from_str(&tokio::fs::read_to_string(&token_file).await?)?;
-        if user_token.expires_at > chrono::Local::now() {
-            let id = auth_with_token(ota, &user_token.token).await?;
-            return Ok(id);
+    let endpoint = client.endpoint().to_string();
+    let token_file = token_path(profile, &endpoint)?;
+    // given credentials also renew a cached session that expires mid-run
+    if let (Some(username), Some(password)) = (&args.username, &args.password) {
+        client.set_credentials(username, password);
+    }
+    let path = token_file.clone();
+    client.on_login(move |username, organization_id, login| {
+        save_token(&path, &endpoint, username, organization_id, login)
+    });
+    client.set_organization_id(args.organization_id.unwrap_or(DEFAULT_ORGANIZATION_ID));
+
+    // without a chosen organization or user the cached one is kept
+    let cached = read_token(&token_file).filter(|t| {
+        t.endpoint == client.endpoint()
+            && args
+                .organization_id
+                .is_none_or(|id| id == t.organization_id)
+            && args.username.as_ref().is_none_or(|u| *u == t.username)
+    });
+    if cli::interactive() {
+        // a session resumed from the cached token has no password to renew it with
+        let username = args.username.clone().or_else(|| {
+            cached
+                .as_ref()
+                .map(|t| t.username.clone())
+                .filter(|u| !u.is_empty())
+        });
+        let password = args.password.clone();
+        client.on_expired(move || {
+            tracing::warn!("session expired, please log in again");
+            let username = username.clone().map_or_else(get_username, Ok);
+            let password = password.clone().map_or_else(get_password, Ok);
+            match (username, password) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                _ => None,
+            }
+        });
+    }
+    if let Some(user_token) = cached {
+        match user_token.expires_at {
+            Some(at) if at <= chrono::Local::now() => {
+                tracing::info!("cached token expired at {}, login again", at)
+            }
+            _ => match client.login_with_token(&user_token.token).await {
+                Ok(id) => {
+                    client.set_organization_id(user_token.organization_id);
+                    return Ok(id);
+                }
+                Err(e @ (OtaError::AuthExpired(_) | OtaError::Server { .. })) => {
+                    tracing::warn!("cached token rejected: {}, login again", e);
+                }
+                Err(e) => return Err(e.into()),
+            },
         }
     }
 
-    let username = get_username()?;
-    let password = get_password()?;
-    let (id, token) = auth_with_password(ota, &username, &password).await?;
-    tokio::fs::create_dir_all(token_file.parent().unwrap()).await?;
-    let expire_time = chrono::Duration::minutes(15);
-    let user_token = UserToken {
-        token: token,
-        expires_at: chrono::Local::now() + expire_time,
+    let username = cli::or_prompt(args.username.clone(), "--username", get_username)?;
+    let password = cli::or_prompt(args.password.clone(), "--password", get_password)?;
+    let mut chosen = args.organization_id.is_some();
+    let login = match client.login(&username, &password).await {
+        // not a member of the default organization, ask for one
+        Err(OtaError::Server { message, .. }) if !chosen && cli::interactive() => {
+            let refused = format!(
+                "login to organization {} failed: {}",
+                client.organization_id(),
+                message
+            );
+            tracing::warn!("{}", refused);
+            let id = get_organization_id(&refused)?.ok_or_else(|| anyhow::anyhow!(refused))?;
+            client.set_organization_id(id);
+            chosen = true;
+            client.login(&username, &password).await
+        }
+        login => login,
     };
-    tokio::fs::write(token_file, serde_json::to_string(&user_token)?).await?;
-    tracing::info!("auth success");
-    Ok(id)
+    let mut login = login
+        .inspect_err(|_| tracing::error!("auth failed, please check username and password"))?;
+    if !chosen && login.organizations.len() > 1 && cli::interactive() {
+        let organization = get_organization(login.organizations.clone())?;
+        if organization.id != client.organization_id() {
+            client.set_organization_id(organization.id);
+            login = client.login(&username, &password).await?;
+        }
+    }
+    tracing::info!("auth success, organization {}", client.organization_id());
+    Ok(login.id)
 }
@@ -1,12 +1,18 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::connection::EventType;
+use crate::audit::Event;
+use crate::cli::{self, PublishArgs};
+use crate::error::Result as OtaResult;
+use crate::report::Published;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
-enum PushType {
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, clap::ValueEnum)]
+pub enum PushType {
     None,
     #[serde(rename = "UPGRADE_SELIENT")]
+    #[value(name = "silent")]
     Slient,
     #[serde(rename = "UPGRADE_ENFORCE")]
     Force,
@@ -23,7 +29,8 @@ impl std::fmt::Display for PushType {
 pub struct Carside {
     auto_publish: bool,
     push_type: PushType,
-    can_approve: bool,
+    /// once an approval is refused, later configs are not approved or pushed.
+    can_approve: AtomicBool,
 }
 
 impl Carside {
@@ -34,134 +41,93 @@ impl Carside {
             .unwrap_or(false))
     }
 
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(args: &PublishArgs) -> anyhow::Result<Self> {
         let mut carside = Carside {
-            auto_publish: Carside::get_auto_publish()?,
+            auto_publish: cli::or_prompt(
+                args.auto_publish,
+                "--auto-publish",
+                Carside::get_auto_publish,
+            )?,
             push_type: PushType::None,
-            can_approve: true,
+            can_approve: AtomicBool::new(true),
         };
 
         if carside.auto_publish {
-            carside.push_type = inquire::Select::new(
-                "auto push to car",
-                vec![PushType::None, PushType::Slient, PushType::Force],
-            )
-            .prompt_skippable()?
-            .unwrap_or(PushType::None);
+            carside.push_type = cli::or_prompt(args.push_type, "--push-type", get_push_type)?;
         }
         Ok(carside)
     }
 }
-impl Carside {
-    async fn publish(&self, ota: &super::Ota, vehicle: &super::Vehicle) -> Result<i32> {
-        #[derive(Debug, Serialize, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct RequsetBody {
-            modify_user_id: i32,
-            vehicle_id: i32,
-            bucket_name: String,
-            key: String,
-            name: String,
-            for_test: i32,
-        }
-        #[derive(Debug, Serialize, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct ResponseBody {
-            id: i32,
-            vehicle_id: i32,
-        }
-        let now = chrono::Local::now();
-        let name = format!(
-            "{}-{}{}-a.tar.gz",
-            vehicle.name,
-            now.format("%Y%m%d%%H%M%S"),
-            ota.user_id
-        );
 
-        let req_body = RequsetBody {
-            modify_user_id: ota.user_id,
-            vehicle_id: vehicle.id,
-            for_test: 1,
-            bucket_name: "zelos-config".to_string(),
-            key: name.clone(),
-            name,
-        };
-        let resp: ResponseBody = ota
-            .conn
-            .request(EventType::OtaAddConfigurePublish, "", &req_body)
-            .await?;
-        Ok(resp.id)
-    }
-
-    async fn approve(&self, ota: &super::Ota, conf_id: i32) -> Result<bool> {
-        #[derive(Debug, Serialize, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct ReqBody {
-            id: i32,
-            is_approve: bool,
-            approver_id: i32,
-        }
-        #[derive(Debug, Serialize, Deserialize)]
-        struct ResponseBody {
-            ok: bool,
+pub fn get_push_type() -> Result<PushType> {
+    Ok(inquire::Select::new(
+        "auto push to car",
+        vec![PushType::None, PushType::Slient, PushType::Force],
+    )
+    .prompt_skippable()?
+    .unwrap_or(PushType::None))
+}
+impl Carside {
+    /// publish, approve and push a saved config as configured, `None` without auto publish.
+    pub async fn process(
+        &self,
+        ota: &super::Ota,
+        vehicle: &super::Vehicle,
+    ) -> OtaResult<Option<Published>> {
+        if !self.auto_publish {
+            return Ok(None);
         }
-        let req = ReqBody {
-            id: conf_id,
-            is_approve: true,
-            approver_id: ota.user_id,
+        let conf_id = ota.client().await.publish_config(vehicle).await?;
+        ota.audit(vehicle, Event::Publish { config_id: conf_id });
+        let mut published = Published {
+            config_id: conf_id,
+            approved: None,
+            push_type: self.push_type,
+            pushed: None,
         };
-
-        let resp: ResponseBody = ota
-            .conn
-            .request(EventType::OtaEditConfigurePublish, "", &req)
-            .await?;
-        Ok(resp.ok)
-    }
-
-    async fn push(&self, ota: &super::Ota, vehicle: &super::Vehicle, conf_id: i32) -> Result<bool> {
-        #[derive(Debug, Serialize, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct ReqBody {
-            vehicle_id: i32,
-            modify_user_id: i32,
-            vehicle_zelos_configure_build_history_id: i32,
-            command_type: PushType,
-        }
-        #[derive(Debug, Serialize, Deserialize)]
-        struct ResponseBody {
-            ok: bool,
+        if self.can_approve.load(Ordering::Relaxed) {
+            let ok = ota.client().await.approve_config(conf_id).await?;
+            ota.audit(
+                vehicle,
+                Event::Approve {
+                    config_id: conf_id,
+                    ok,
+                },
+            );
+            published.approved = Some(ok);
+            if !ok {
+                self.can_approve.store(false, Ordering::Relaxed);
+            }
         }
-        let req = ReqBody {
-            vehicle_id: vehicle.id,
-            modify_user_id: ota.user_id,
-            vehicle_zelos_configure_build_history_id: conf_id,
-            command_type: self.push_type,
-        };
-        let resp: ResponseBody = ota
-            .conn
-            .request(EventType::OtaConfigurePublish, "", &req)
-            .await?;
-        Ok(resp.ok)
-    }
-
-    pub async fn process(&mut self, ota: &super::Ota, vehicle: &super::Vehicle) -> Result<()> {
-        if self.auto_publish {
-            let conf_id = self.publish(ota, vehicle).await?;
-            self.can_approve = self.can_approve && self.approve(ota, conf_id).await?;
-            if self.push_type != PushType::None {
-                if !self.can_approve {
-                    tracing::warn!(
-                        "skip push {} to {}, because can not approve",
-                        conf_id,
-                        vehicle.id
-                    );
-                } else if self.push(ota, vehicle, conf_id).await? {
-                    tracing::info!("success push {} to {}", conf_id, vehicle.id);
-                } else {
-                    tracing::error!("push conf {} to {} faild.", conf_id, vehicle.id);
-                }
+        if self.push_type != PushType::None {
+            if !self.can_approve.load(Ordering::Relaxed) {
+                tracing::warn!(
+                    "skip push {} to {}, because can not approve",
+                    conf_id,
+                    vehicle.id
+                );
+                return Ok(Some(published));
+            }
+            let ok = ota
+                .client()
+                .await
+                .push_config(vehicle.id, conf_id, self.push_type)
+                .await?;
+            ota.audit(
+                vehicle,
+                Event::Push {
+                    config_id: conf_id,
+                    push_type: self.push_type,
+                    ok,
+                },
+            );
+            published.pushed = Some(ok);
+            if ok {
+                tracing::info!("success push {} to {}", conf_id, vehicle.id);
+            } else {
+                tracing::error!("push conf {} to {} faild.", conf_id, vehicle.id);
             }
         }
-        Ok(())
+        Ok(Some(published))
     }
 }
@@ -0,0 +1,133 @@
+//! record the frames of a session to a jsonl cassette and replay them.
+//!
+//! each line is `{"dir":"send","frame":<request>}` or `{"dir":"recv","frame":<response>}`,
+//! keep alives are left out. `Recording` wraps the transport of a session,
+//! `ReplayServer` serves the recorded responses of each request in order,
+//! so a session seen against the real server becomes a test. `ReplayServer`
+//! needs the `test-support` feature.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::connection::{EventType, Request, Response};
+use crate::error::{OtaError, Result};
+use crate::transport::{Link, Transport};
+
+#[cfg(feature = "test-support")]
+mod replay;
+#[cfg(feature = "test-support")]
+pub use replay::ReplayServer;
+
+/// request body and response data fields never written to a cassette.
+const REDACTED: &[&str] = &["password", "token"];
+
+fn redact(object: &mut serde_json::Map<String, Value>) {
+    for field in REDACTED {
+        if let Some(value) = object.get_mut(*field) {
+            *value = Value::String("***".to_string());
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "dir", content = "frame", rename_all = "lowercase")]
+enum Entry {
+    Send(Request),
+    Recv(Response),
+}
+
+/// a transport writing every frame of `inner` to a cassette.
+pub struct Recording {
+    inner: Arc<dyn Transport>,
+    recorder: Recorder,
+}
+
+impl Recording {
+    pub fn new(inner: Arc<dyn Transport>, path: &Path) -> Result<Recording> {
+        Ok(Recording {
+            inner,
+            recorder: Recorder::create(path)?,
+        })
+    }
+}
+
+impl Transport for Recording {
+    fn endpoint(&self) -> &str {
+        self.inner.endpoint()
+    }
+
+    fn open(&self) -> BoxFuture<'_, Result<Link>> {
+        Box::pin(async move {
+            let Link { tx, rx } = self.inner.open().await?;
+            let (link, requests, responses) = Link::pair();
+            let recorder = self.recorder.clone();
+            tokio::spawn(requests.for_each(move |req| {
+                recorder.sent(&req);
+                let _ = tx.unbounded_send(req);
+                futures::future::ready(())
+            }));
+            let recorder = self.recorder.clone();
+            tokio::spawn(rx.for_each(move |resp| {
+                recorder.received(&resp);
+                let _ = responses.unbounded_send(resp);
+                futures::future::ready(())
+            }));
+            Ok(link)
+        })
+    }
+}
+
+/// appends frames to a cassette file.
+#[derive(Clone)]
+struct Recorder {
+    file: Arc<Mutex<std::fs::File>>,
+}
+
+impl Recorder {
+    fn create(path: &Path) -> Result<Recorder> {
+        let file = std::fs::File::create(path).map_err(|e| {
+            OtaError::Transport(format!("create cassette {:?} failed: {}", path, e))
+        })?;
+        tracing::info!("record session to {:?}", path);
+        Ok(Recorder {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    fn sent(&self, req: &Request) {
+        if req.event_type == EventType::KeepAlive {
+            return;
+        }
+        let mut req = req.clone();
+        if let Ok(Value::Object(mut body)) = serde_json::from_str(&req.request_body) {
+            redact(&mut body);
+            req.request_body = Value::Object(body).to_string();
+        }
+        self.write(&Entry::Send(req));
+    }
+
+    fn received(&self, resp: &Response) {
+        if resp.event_type == EventType::KeepAlive {
+            return;
+        }
+        let mut resp = resp.clone();
+        if let Some(Value::Object(data)) = &mut resp.data {
+            redact(data);
+        }
+        self.write(&Entry::Recv(resp));
+    }
+
+    fn write(&self, entry: &Entry) {
+        let line = serde_json::to_string(entry).unwrap();
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            tracing::warn!("write cassette failed: {}", e);
+        }
+    }
+}
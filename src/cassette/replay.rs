@@ -0,0 +1,202 @@
+//! serve a recorded cassette back to a client.
+
+use std::collections::{HashSet, VecDeque};
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use super::{Entry, REDACTED};
+use crate::connection::{EventType, Request, Response};
+
+/// a recorded request and the responses that followed it.
+#[derive(Debug)]
+struct Exchange {
+    request: Request,
+    responses: Vec<Response>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    exchanges: VecDeque<Exchange>,
+    ignored: HashSet<String>,
+    mismatches: Vec<String>,
+    connections: Vec<JoinHandle<()>>,
+}
+
+/// a websocket server replaying a cassette, stopped on drop.
+///
+/// requests must come in the recorded order with the recorded event type,
+/// a different body is noted in `mismatches` but still answered.
+pub struct ReplayServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl ReplayServer {
+    /// load `path` and listen on a free local port.
+    pub async fn start(path: &Path) -> std::io::Result<ReplayServer> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut exchanges: VecDeque<Exchange> = VecDeque::new();
+        for line in file.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line)? {
+                Entry::Send(request) => exchanges.push_back(Exchange {
+                    request,
+                    responses: vec![],
+                }),
+                Entry::Recv(response) => match exchanges.back_mut() {
+                    Some(exchange) => exchange.responses.push(response),
+                    None => tracing::warn!("skip response before any request: {}", line),
+                },
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            exchanges,
+            ignored: REDACTED.iter().map(|f| f.to_string()).collect(),
+            ..Default::default()
+        }));
+        let s = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let conn = tokio::spawn(serve(s.clone(), stream));
+                s.lock().unwrap().connections.push(conn);
+            }
+        });
+        Ok(ReplayServer { addr, state, task })
+    }
+
+    /// the endpoint to pass as `--endpoint`.
+    pub fn url(&self) -> String {
+        format!("ws://{}/user_client", self.addr)
+    }
+
+    /// don't compare this top level request body field, e.g. one holding a timestamp.
+    pub fn ignore_field(self, field: &str) -> Self {
+        self.state.lock().unwrap().ignored.insert(field.to_string());
+        self
+    }
+
+    /// differences between the live requests and the cassette.
+    pub fn mismatches(&self) -> Vec<String> {
+        self.state.lock().unwrap().mismatches.clone()
+    }
+
+    /// recorded requests not asked for yet.
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().exchanges.len()
+    }
+}
+
+impl Drop for ReplayServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        for conn in self.state.lock().unwrap().connections.drain(..) {
+            conn.abort();
+        }
+    }
+}
+
+async fn serve(state: Arc<Mutex<State>>, stream: TcpStream) {
+    let mut ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            tracing::warn!("replay accept failed: {}", e);
+            return;
+        }
+    };
+    while let Some(Ok(msg)) = ws.next().await {
+        let req: Request = match msg {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(req) => req,
+                Err(e) => {
+                    tracing::warn!("replay got bad request {}: {}", text, e);
+                    continue;
+                }
+            },
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let responses = if req.event_type == EventType::KeepAlive {
+            vec![Response {
+                event_type: EventType::KeepAlive,
+                error_code: 0,
+                message: None,
+                data: None,
+            }]
+        } else {
+            state.lock().unwrap().answer(&req)
+        };
+        for resp in responses {
+            let text = serde_json::to_string(&resp).unwrap();
+            if ws.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl State {
+    fn answer(&mut self, req: &Request) -> Vec<Response> {
+        let exchange = match self.exchanges.front() {
+            Some(exchange) if exchange.request.event_type == req.event_type => {
+                self.exchanges.pop_front().unwrap()
+            }
+            other => {
+                let mismatch = format!(
+                    "expect {:?}, got {:?}",
+                    other.map(|e| e.request.event_type),
+                    req.event_type
+                );
+                tracing::error!("replay: {}", mismatch);
+                self.mismatches.push(mismatch.clone());
+                return vec![Response {
+                    event_type: req.event_type,
+                    error_code: 500,
+                    message: Some(format!("cassette mismatch: {}", mismatch)),
+                    data: None,
+                }];
+            }
+        };
+        if exchange.request.path_parameter != req.path_parameter {
+            self.mismatches.push(format!(
+                "{:?} path: expect {}, got {}",
+                req.event_type, exchange.request.path_parameter, req.path_parameter
+            ));
+        }
+        let expect = self.body(&exchange.request.request_body);
+        let got = self.body(&req.request_body);
+        if expect != got {
+            self.mismatches.push(format!(
+                "{:?} body: expect {}, got {}",
+                req.event_type, expect, got
+            ));
+        }
+        exchange.responses
+    }
+
+    /// a request body as json without the ignored fields.
+    fn body(&self, body: &str) -> Value {
+        match serde_json::from_str(body) {
+            Ok(Value::Object(mut body)) => {
+                body.retain(|field, _| !self.ignored.contains(field));
+                Value::Object(body)
+            }
+            Ok(other) => other,
+            Err(_) => Value::String(body.to_string()),
+        }
+    }
+}
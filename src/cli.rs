@@ -0,0 +1,322 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+
+use crate::carside::PushType;
+use crate::mode::Mode;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "edit vehicle yaml on the ota server")]
+pub struct Cli {
+    #[command(flatten)]
+    pub conn: ConnArgs,
+    #[command(flatten)]
+    pub login: LoginArgs,
+    /// named host and account from ~/.config/ota-yaml/config.yaml
+    #[arg(long, env = "OTA_PROFILE", global = true)]
+    pub profile: Option<String>,
+    /// vehicles processed at the same time, each over its own connection
+    #[arg(
+        short,
+        long,
+        env = "OTA_JOBS",
+        global = true,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub jobs: u16,
+    /// also report the result of each vehicle for ci
+    #[arg(long, env = "OTA_OUTPUT", global = true, value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+    /// write the json or junit report here instead of stdout
+    #[arg(long, env = "OTA_OUTPUT_FILE", global = true)]
+    pub output_file: Option<PathBuf>,
+    /// what to do with the remaining vehicles after one failed
+    #[arg(
+        long,
+        env = "OTA_ON_FAILURE",
+        global = true,
+        value_enum,
+        default_value_t = OnFailure::Ask
+    )]
+    pub on_failure: OnFailure,
+    /// retry a failed fetch or save this many times first
+    #[arg(long, env = "OTA_RETRIES", global = true, default_value_t = 0)]
+    pub retries: u32,
+    /// without a command the interactive edit flow runs
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConnArgs {
+    /// ota host, connects to ws://<host>:8090/user_client
+    #[arg(long, env = "OTA_HOST", global = true)]
+    pub host: Option<String>,
+    /// full endpoint url, wins over --host
+    #[arg(long, env = "OTA_ENDPOINT", global = true)]
+    pub endpoint: Option<String>,
+    /// extra PEM bundle to trust for wss://
+    #[arg(long, env = "OTA_CA_FILE", global = true)]
+    pub ca_file: Option<PathBuf>,
+    /// skip certificate verification, lab servers only
+    #[arg(
+        long,
+        env = "OTA_INSECURE",
+        global = true,
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    pub insecure: bool,
+    /// seconds to wait for each reply
+    #[arg(long, env = "OTA_TIMEOUT", global = true)]
+    pub timeout: Option<u64>,
+    /// server error code of an expired session, 401, 403 and errors about
+    /// the token or session are taken as expired anyway
+    #[arg(long, env = "OTA_EXPIRED_CODE", global = true)]
+    pub expired_code: Option<i32>,
+    /// write every request and response to this jsonl cassette, passwords redacted
+    #[arg(long, env = "OTA_RECORD", global = true)]
+    pub record: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct LoginArgs {
+    #[arg(long, env = "OTA_USERNAME", global = true)]
+    pub username: Option<String>,
+    #[arg(long, env = "OTA_PASSWORD", global = true, hide_env_values = true)]
+    pub password: Option<String>,
+    /// organization to log into, asked for if the server lists several, 1 otherwise
+    #[arg(long, env = "OTA_ORGANIZATION_ID", global = true)]
+    pub organization_id: Option<i32>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// log in and cache the session token
+    Login,
+    /// query vehicles
+    Vehicles {
+        #[command(subcommand)]
+        command: VehiclesCommand,
+    },
+    /// print the yaml of vehicles or download it into a directory
+    Get(GetArgs),
+    /// edit vehicles with the process modes
+    Edit(EditArgs),
+    /// set values in the yaml of vehicles
+    Set(SetArgs),
+    /// upload changed `<vehicle>.yaml` files, refusing to overwrite newer server edits
+    Apply(ApplyArgs),
+    /// restore vehicles to their config before a journaled save
+    Rollback(RollbackArgs),
+    /// publish and approve the saved config of vehicles
+    Publish(SelectArgs),
+    /// publish, approve and push the saved config of vehicles
+    Push(PushArgs),
+    /// query the local log of saves, publishes and pushes
+    Audit(AuditArgs),
+    /// named hosts and accounts
+    Profiles {
+        #[command(subcommand)]
+        command: ProfilesCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VehiclesCommand {
+    /// list id and name of all vehicles
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfilesCommand {
+    /// list the profiles of the config file
+    List,
+}
+
+#[derive(Debug, Args, Default)]
+pub struct SelectArgs {
+    /// vehicle names, asked for when missing
+    #[arg(short, long = "vehicle", env = "OTA_VEHICLES", value_delimiter = ',')]
+    pub vehicles: Vec<String>,
+    /// select all vehicles
+    #[arg(long, conflicts_with = "vehicles")]
+    pub all: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GetArgs {
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// write `<vehicle>.yaml` files into this directory instead of stdout
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+    /// also write a `<vehicle>.meta.json` with vehicle id, fetch time and hash
+    #[arg(long, requires = "output_dir")]
+    pub metadata: bool,
+}
+
+#[derive(Debug, Args, Default)]
+pub struct ProcessArgs {
+    /// confirm every edit with a preview
+    #[arg(long, env = "OTA_MANUAL", conflicts_with = "plan")]
+    pub manual: Option<bool>,
+    /// fetch and edit all vehicles first, then confirm one combined diff before saving
+    #[arg(long)]
+    pub plan: bool,
+    /// commit the plan without asking
+    #[arg(short, long, requires = "plan")]
+    pub yes: bool,
+    /// when a vehicle fails, restore the ones already saved in this run
+    #[arg(long)]
+    pub rollback: bool,
+    #[command(flatten)]
+    pub publish: PublishArgs,
+}
+
+#[derive(Debug, Args, Default)]
+pub struct PublishArgs {
+    /// publish and approve after save
+    #[arg(long, env = "OTA_AUTO_PUBLISH")]
+    pub auto_publish: Option<bool>,
+    /// push the published config to the car
+    #[arg(long, env = "OTA_PUSH_TYPE", value_enum)]
+    pub push_type: Option<PushType>,
+}
+
+#[derive(Debug, Args, Default)]
+pub struct EditArgs {
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// modes applied in order, asked for when missing
+    #[arg(long = "mode", value_enum)]
+    pub modes: Vec<Mode>,
+    /// enabled components for the components mode
+    #[arg(long, value_delimiter = ',')]
+    pub components: Option<Vec<String>>,
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SetArgs {
+    #[command(flatten)]
+    pub select: SelectArgs,
+    /// `path=value` pairs, the path is dot separated and a
+    /// sequence is indexed by position or by item name
+    #[arg(required = true)]
+    pub assignments: Vec<String>,
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ApplyArgs {
+    /// yaml files named after their vehicle, or directories of them
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// what to do when the server copy changed since download, asked for when missing
+    #[arg(long, value_enum)]
+    pub on_conflict: Option<Conflict>,
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    /// undo the last save of these vehicles
+    #[arg(short, long = "vehicle", value_delimiter = ',', conflicts_with = "run")]
+    pub vehicles: Vec<String>,
+    /// undo every save of this run, the id is printed after each run
+    #[arg(long)]
+    pub run: Option<String>,
+    /// list the journaled runs
+    #[arg(long, conflicts_with_all = ["vehicles", "run"])]
+    pub list: bool,
+    /// restore even if the vehicle changed after the journaled save
+    #[arg(long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnFailure {
+    /// stop, the remaining vehicles are not reached
+    Abort,
+    /// go on with the remaining vehicles
+    Continue,
+    /// ask, abort without a terminal
+    Ask,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    /// only the log summary
+    Text,
+    Json,
+    Junit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Conflict {
+    /// leave the vehicle untouched
+    Refuse,
+    /// replay the local changes onto the server copy, fails on overlapping edits
+    Merge,
+    /// replace the server copy with the local file
+    Overwrite,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::Refuse => write!(f, "refuse"),
+            Conflict::Merge => write!(f, "merge"),
+            Conflict::Overwrite => write!(f, "overwrite"),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PushArgs {
+    #[command(flatten)]
+    pub select: SelectArgs,
+    #[arg(long, env = "OTA_PUSH_TYPE", value_enum)]
+    pub push_type: Option<PushType>,
+}
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// only this vehicle
+    #[arg(short, long)]
+    pub vehicle: Option<String>,
+    /// only this user id
+    #[arg(short, long)]
+    pub user: Option<i32>,
+    /// from this day on, e.g. 2024-01-31
+    #[arg(long)]
+    pub since: Option<chrono::NaiveDate>,
+    /// up to and including this day
+    #[arg(long)]
+    pub until: Option<chrono::NaiveDate>,
+    /// print the raw json records
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// whether prompts can be shown.
+pub fn interactive() -> bool {
+    std::io::stdin().is_terminal()
+}
+
+/// use `value` when given, otherwise `prompt` if a terminal is attached.
+pub fn or_prompt<T>(value: Option<T>, flag: &str, prompt: impl FnOnce() -> Result<T>) -> Result<T> {
+    match value {
+        Some(value) => Ok(value),
+        None if interactive() => prompt(),
+        None => Err(anyhow::anyhow!("{} is required without a terminal", flag)),
+    }
+}
@@ -0,0 +1,355 @@
+//! async calls to the ota backend, without prompts or local state.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), ota_yaml::OtaError> {
+//! use ota_yaml::transport::TlsOptions;
+//! use ota_yaml::OtaClient;
+//!
+//! let mut client = OtaClient::connect(
+//!     "ws://ota.example.com:8090/user_client",
+//!     &TlsOptions::default(),
+//!     std::time::Duration::from_secs(30),
+//! )
+//! .await?;
+//! client.login("ci", "secret").await?;
+//! for vehicle in client.list_vehicles().await? {
+//!     let yaml = client.fetch_template(vehicle.id).await?;
+//!     println!("# {}\n{}", vehicle.name, yaml);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::api::*;
+use crate::carside::PushType;
+use crate::connection::{Connenction, EventType};
+use crate::error::{OtaError, Result};
+use crate::transport::{TlsOptions, Transport, WebSocket};
+
+/// called with the username, organization id and reply of every successful
+/// password login, e.g. to cache the token.
+type OnLogin = Arc<dyn Fn(&str, i32, &LoginResponse) + Send + Sync>;
+
+/// asked for a username and password when the session expires and none are known,
+/// `None` to give up.
+type OnExpired = Arc<dyn Fn() -> Option<(String, String)> + Send + Sync>;
+
+/// the organization logged into unless `set_organization_id` says otherwise.
+pub const DEFAULT_ORGANIZATION_ID: i32 = 1;
+
+/// a logged in session, `login` or `login_with_token` comes first.
+pub struct OtaClient {
+    conn: Connenction,
+    user_id: i32,
+    organization_id: i32,
+    /// reply timeouts replacing the default for some event types.
+    timeouts: HashMap<EventType, Duration>,
+    /// server error code of an expired session, besides the ones `OtaError` knows.
+    expired_code: Option<i32>,
+    /// username and password to log in again when the session expires.
+    credentials: Mutex<Option<(String, String)>>,
+    on_login: Option<OnLogin>,
+    on_expired: Option<OnExpired>,
+    /// one renewal at a time, the others use its credentials.
+    renewal: tokio::sync::Mutex<()>,
+}
+
+impl OtaClient {
+    /// connect to a `ws://` or `wss://` endpoint, `timeout` bounds every reply.
+    pub async fn connect(url: &str, tls: &TlsOptions, timeout: Duration) -> Result<OtaClient> {
+        OtaClient::with_transport(Arc::new(WebSocket::new(url, tls)?), timeout).await
+    }
+
+    /// connect over any transport, e.g. an in-process fake.
+    pub async fn with_transport(
+        transport: Arc<dyn Transport>,
+        timeout: Duration,
+    ) -> Result<OtaClient> {
+        Ok(OtaClient {
+            conn: Connenction::new(transport, timeout).await?,
+            user_id: -1,
+            organization_id: DEFAULT_ORGANIZATION_ID,
+            timeouts: HashMap::new(),
+            expired_code: None,
+            credentials: Mutex::new(None),
+            on_login: None,
+            on_expired: None,
+            renewal: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// id of the logged in user, -1 before login.
+    pub fn user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    /// where the client is connected, e.g. the websocket url.
+    pub fn endpoint(&self) -> &str {
+        self.conn.endpoint()
+    }
+
+    /// the organization the next password login goes to.
+    pub fn set_organization_id(&mut self, organization_id: i32) {
+        self.organization_id = organization_id;
+    }
+
+    pub fn organization_id(&self) -> i32 {
+        self.organization_id
+    }
+
+    /// wait `timeout` for replies to `event_type` instead of the default,
+    /// e.g. longer for saves of large templates.
+    pub fn set_timeout(&mut self, event_type: EventType, timeout: Duration) {
+        self.timeouts.insert(event_type, timeout);
+    }
+
+    /// also take this server error code as an expired session.
+    pub fn set_expired_code(&mut self, code: i32) {
+        self.expired_code = Some(code);
+    }
+
+    /// log in again with these when the server reports an expired session,
+    /// `login` remembers its own.
+    pub fn set_credentials(&mut self, username: &str, password: &str) {
+        *self.credentials.get_mut().unwrap() = Some((username.to_string(), password.to_string()));
+    }
+
+    /// run `f` after every password login, including the ones after an expiry.
+    pub fn on_login(&mut self, f: impl Fn(&str, i32, &LoginResponse) + Send + Sync + 'static) {
+        self.on_login = Some(Arc::new(f));
+    }
+
+    /// ask `f` for the credentials when the session expires without known ones,
+    /// e.g. a session resumed from a cached token.
+    pub fn on_expired(&mut self, f: impl Fn() -> Option<(String, String)> + Send + Sync + 'static) {
+        self.on_expired = Some(Arc::new(f));
+    }
+
+    /// log in with a password, the session is resumed after a reconnect
+    /// and renewed when it expires.
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<LoginResponse> {
+        let resp = self.password_login(username, password).await?;
+        self.user_id = resp.id;
+        self.set_credentials(username, password);
+        Ok(resp)
+    }
+
+    async fn password_login(&self, username: &str, password: &str) -> Result<LoginResponse> {
+        let req = LoginRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+            organization_id: self.organization_id,
+        };
+        let resp: LoginResponse = self.request(EventType::LoginRequest, "", &req).await?;
+        self.conn.set_token(&resp.token);
+        if let Some(on_login) = &self.on_login {
+            on_login(username, self.organization_id, &resp);
+        }
+        Ok(resp)
+    }
+
+    async fn request<T, D>(&self, event_type: EventType, path: &str, body: &T) -> Result<D>
+    where
+        T: Serialize + std::fmt::Debug,
+        D: DeserializeOwned + std::fmt::Debug,
+    {
+        let result = match self.timeouts.get(&event_type) {
+            Some(timeout) => {
+                self.conn
+                    .request_with_timeout(event_type, path, body, *timeout)
+                    .await
+            }
+            None => self.conn.request(event_type, path, body).await,
+        };
+        match result {
+            Err(OtaError::Server { code, message }) if self.expired_code == Some(code) => {
+                Err(OtaError::AuthExpired(message))
+            }
+            result => result,
+        }
+    }
+
+    /// `request` of a logged in session, an expired one is renewed once
+    /// if the credentials are known or `on_expired` gives them.
+    async fn call<T, D>(&self, event_type: EventType, path: &str, body: &T) -> Result<D>
+    where
+        T: Serialize + std::fmt::Debug,
+        D: DeserializeOwned + std::fmt::Debug,
+    {
+        match self.request(event_type, path, body).await {
+            Err(OtaError::AuthExpired(e)) => {
+                tracing::warn!("session expired: {}, login again", e);
+                self.renew(e).await?;
+                self.request(event_type, path, body).await
+            }
+            result => result,
+        }
+    }
+
+    async fn renew(&self, expired: String) -> Result<()> {
+        let _renewal = self.renewal.lock().await;
+        let known = self.credentials.lock().unwrap().clone();
+        let Some((username, password)) =
+            known.or_else(|| self.on_expired.as_ref().and_then(|ask| ask()))
+        else {
+            return Err(OtaError::AuthExpired(expired));
+        };
+        self.password_login(&username, &password).await?;
+        *self.credentials.lock().unwrap() = Some((username, password));
+        Ok(())
+    }
+
+    /// another connection logged in with the token of this one, with its own
+    /// session on the server. requests of one kind go one at a time per
+    /// connection, so parallel jobs need one each.
+    pub async fn connect_again(&self) -> Result<OtaClient> {
+        let token = self
+            .conn
+            .token()
+            .ok_or_else(|| OtaError::Protocol("not logged in".to_string()))?;
+        let mut client = OtaClient {
+            conn: self.conn.reopen().await?,
+            user_id: -1,
+            organization_id: self.organization_id,
+            timeouts: self.timeouts.clone(),
+            expired_code: self.expired_code,
+            credentials: Mutex::new(self.credentials.lock().unwrap().clone()),
+            on_login: self.on_login.clone(),
+            on_expired: self.on_expired.clone(),
+            renewal: tokio::sync::Mutex::new(()),
+        };
+        client.login_with_token(&token).await?;
+        Ok(client)
+    }
+
+    /// log in with the token of an earlier `login`,
+    /// `OtaError::AuthExpired` if the server forgot it.
+    pub async fn login_with_token(&mut self, token: &str) -> Result<i32> {
+        let req = TokenLoginRequest {
+            token: token.to_string(),
+        };
+        let resp: TokenLoginResponse = self.request(EventType::TokenLoginRequest, "", &req).await?;
+        self.conn.set_token(token);
+        self.user_id = resp.id;
+        Ok(resp.id)
+    }
+
+    pub async fn list_vehicles(&self) -> Result<Vec<Vehicle>> {
+        let req = VehicleTableRequest {
+            user_id: self.user_id,
+            station_id: "".to_string(),
+            name: "".to_string(),
+        };
+        let resp: VehicleTable = self
+            .call(
+                EventType::OtaFetchVehicleTemplateTable,
+                "?pageSize=10000",
+                &req,
+            )
+            .await?;
+        tracing::info!(
+            "get {} vehicles from ota, total {}",
+            resp.list.len(),
+            resp.total
+        );
+        Ok(resp.list)
+    }
+
+    /// the yaml text of a vehicle as stored, it may not parse.
+    pub async fn fetch_template(&self, vehicle_id: i32) -> Result<String> {
+        let req = TemplateRequest { vehicle_id };
+        let text: serde_json::Value = self
+            .call(EventType::OtaFetchVehicleTemplateItemContents, "", &req)
+            .await?;
+        match text {
+            serde_json::Value::String(text) => Ok(text),
+            other => Err(OtaError::Protocol(format!(
+                "expect yaml text of vehicle {}, got {}",
+                vehicle_id, other
+            ))),
+        }
+    }
+
+    /// replace the yaml of a vehicle, `old` is the text it is based on.
+    pub async fn save_template(
+        &self,
+        vehicle_id: i32,
+        old: &str,
+        new: &str,
+    ) -> Result<SaveTemplateResponse> {
+        let req = SaveTemplateRequest {
+            user_id: self.user_id,
+            vehicle_id,
+            old_config: old.to_string(),
+            new_config: new.to_string(),
+        };
+        self.call(EventType::OtaAddVehicleTemplateItem, "", &req)
+            .await
+    }
+
+    /// build the saved yaml of a vehicle into a config archive, returns its id.
+    pub async fn publish_config(&self, vehicle: &Vehicle) -> Result<i32> {
+        let now = chrono::Local::now();
+        let name = format!(
+            "{}-{}{}-a.tar.gz",
+            vehicle.name,
+            now.format("%Y%m%d%%H%M%S"),
+            self.user_id
+        );
+        let req = PublishRequest {
+            modify_user_id: self.user_id,
+            vehicle_id: vehicle.id,
+            for_test: 1,
+            bucket_name: "zelos-config".to_string(),
+            key: name.clone(),
+            name,
+        };
+        let resp: PublishResponse = self
+            .call(EventType::OtaAddConfigurePublish, "", &req)
+            .await?;
+        if resp.vehicle_id != vehicle.id {
+            return Err(OtaError::Protocol(format!(
+                "publish of vehicle {} answered for vehicle {}",
+                vehicle.id, resp.vehicle_id
+            )));
+        }
+        Ok(resp.id)
+    }
+
+    /// approve a published config, false if the server refused.
+    pub async fn approve_config(&self, config_id: i32) -> Result<bool> {
+        let req = ApproveRequest {
+            id: config_id,
+            is_approve: true,
+            approver_id: self.user_id,
+        };
+        let resp: OkResponse = self
+            .call(EventType::OtaEditConfigurePublish, "", &req)
+            .await?;
+        Ok(resp.ok)
+    }
+
+    /// push an approved config to the car, false if the server refused.
+    pub async fn push_config(
+        &self,
+        vehicle_id: i32,
+        config_id: i32,
+        push_type: PushType,
+    ) -> Result<bool> {
+        let req = PushRequest {
+            vehicle_id,
+            modify_user_id: self.user_id,
+            vehicle_zelos_configure_build_history_id: config_id,
+            command_type: push_type,
+        };
+        let resp: OkResponse = self.call(EventType::OtaConfigurePublish, "", &req).await?;
+        Ok(resp.ok)
+    }
+}
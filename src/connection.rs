@@ -1,52 +1,205 @@
-use anyhow::Result;
-use futures::{StreamExt, TryStreamExt};
-use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use crate::error::{OtaError, Result};
+use crate::transport::{Link, Transport};
+use futures::StreamExt;
+use futures_channel::mpsc::UnboundedSender;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
 
-#[derive(Debug, Clone)]
+/// waiters for in-flight requests of the current link.
+type Pending = Arc<Mutex<Waiters>>;
+
+/// one turn per event type, held while a request of that type is in flight.
+type Turns = Arc<Mutex<HashMap<EventType, Arc<tokio::sync::Mutex<()>>>>>;
+
+/// at most one in-flight request per event type, see `dispatch`.
+#[derive(Debug, Default)]
+struct Waiters {
+    /// bumped for every link, replies from an older one are dropped.
+    link: u64,
+    waiters: HashMap<EventType, oneshot::Sender<Response>>,
+}
+
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
 pub struct Connenction {
-    tx: UnboundedSender<Message>,
-    rx: Arc<RwLock<UnboundedReceiver<Message>>>,
+    transport: Arc<dyn Transport>,
+    tx: Arc<RwLock<UnboundedSender<Request>>>,
+    online: watch::Receiver<bool>,
+    online_tx: Arc<watch::Sender<bool>>,
+    /// the link was closed by a timeout, reconnect without waiting.
+    dropped: Arc<AtomicBool>,
+    token: Arc<Mutex<Option<String>>>,
+    timeout: Duration,
+    pending: Pending,
+    turns: Turns,
 }
 
 impl Connenction {
-    pub async fn new(host: &str, port: i32, path: &str) -> Result<Connenction> {
-        let url = format!("ws://{}:{}{}", host, port, path);
-        tracing::info!("connent to {}", url);
-        let (stream, _) = connect_async(url).await?;
+    /// open a link over `transport`, it is reopened whenever it closes.
+    /// `timeout` is the default reply timeout of `request`.
+    pub async fn new(transport: Arc<dyn Transport>, timeout: Duration) -> Result<Connenction> {
+        tracing::info!("connent to {}", transport.endpoint());
+        let link = transport.open().await?;
 
-        let (wtx, wrx) = futures_channel::mpsc::unbounded::<Message>();
-        let (rtx, rrx) = futures_channel::mpsc::unbounded::<Message>();
+        let (wtx, _) = futures_channel::mpsc::unbounded::<Request>();
+        let (online_tx, online) = watch::channel(true);
         let conn = Connenction {
-            tx: wtx.clone(),
-            rx: Arc::new(RwLock::new(rrx)),
+            transport,
+            tx: Arc::new(RwLock::new(wtx)),
+            online,
+            online_tx: Arc::new(online_tx),
+            dropped: Arc::new(AtomicBool::new(false)),
+            token: Arc::new(Mutex::new(None)),
+            timeout,
+            pending: Arc::new(Mutex::new(Waiters::default())),
+            turns: Arc::new(Mutex::new(HashMap::new())),
         };
 
-        let (write, read) = stream.split();
-
-        let t = wrx.map(Ok).forward(write);
-        let r = read.try_for_each(move |msg| {
-            tracing::trace!("recv: {:?}", msg);
-            rtx.unbounded_send(msg).unwrap();
-            futures_util::future::ok(())
-        });
-
+        let closed = conn.attach(link);
+        let c = conn.clone();
         tokio::spawn(async move {
-            futures_util::pin_mut!(t, r);
-            futures_util::future::select(t, r).await;
+            c.supervise(closed).await;
         });
         let c = conn.clone();
         tokio::spawn(async move {
-            c.keep_alive().await.expect("keep alive failed");
+            c.keep_alive().await;
         });
-        tracing::info!("websocket connected.");
+        tracing::info!("connected.");
         Ok(conn)
     }
 
-    async fn keep_alive(&self) -> Result<()> {
+    pub fn endpoint(&self) -> &str {
+        self.transport.endpoint()
+    }
+
+    /// remember the session token so a reconnect can log in again.
+    pub fn set_token(&self, token: &str) {
+        *self.token.lock().unwrap() = Some(token.to_string());
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.token.lock().unwrap().clone()
+    }
+
+    /// a second connection over the same transport, with its own link and turns.
+    pub async fn reopen(&self) -> Result<Connenction> {
+        Connenction::new(self.transport.clone(), self.timeout).await
+    }
+
+    /// dispatch responses of `link` until it closes, the handle resolves then.
+    fn attach(&self, link: Link) -> JoinHandle<()> {
+        let Link { tx, mut rx } = link;
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.link += 1;
+            pending.link
+        };
+        *self.tx.write().unwrap() = tx;
+        let c = self.clone();
+        tokio::spawn(async move {
+            while let Some(resp) = rx.next().await {
+                c.dispatch(id, resp);
+            }
+        })
+    }
+
+    /// close `link` if it is still the current one, e.g. after a timeout, since a
+    /// late reply on it can't be told apart from the answer to the next request.
+    /// in-flight requests fail.
+    fn drop_link(&self, link: u64) {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.link != link {
+                return;
+            }
+            pending.link += 1;
+            pending.waiters.clear();
+        }
+        let _ = self.online_tx.send(false);
+        self.dropped.store(true, Ordering::Relaxed);
+        // dropping the sender asks the transport to close the link
+        let (dead, _) = futures_channel::mpsc::unbounded();
+        *self.tx.write().unwrap() = dead;
+    }
+
+    async fn supervise(&self, mut closed: JoinHandle<()>) {
+        loop {
+            let _ = closed.await;
+            tracing::warn!("connection closed, reconnecting...");
+            let _ = self.online_tx.send(false);
+            // drop all waiters so in-flight requests fail instead of hanging
+            self.pending.lock().unwrap().waiters.clear();
+            let first = if self.dropped.swap(false, Ordering::Relaxed) {
+                Duration::ZERO
+            } else {
+                RECONNECT_MIN_DELAY
+            };
+            closed = self.reconnect(first).await;
+            let _ = self.online_tx.send(true);
+            tracing::info!("reconnected.");
+        }
+    }
+
+    async fn reconnect(&self, first: Duration) -> JoinHandle<()> {
+        let mut delay = RECONNECT_MIN_DELAY;
+        tokio::time::sleep(first).await;
+        loop {
+            match self.transport.open().await {
+                Ok(link) => {
+                    let closed = self.attach(link);
+                    if let Err(e) = self.resume().await {
+                        tracing::error!("resume session failed: {}", e);
+                    }
+                    return closed;
+                }
+                Err(e) => {
+                    tracing::warn!("reconnect to {} failed: {}", self.transport.endpoint(), e)
+                }
+            }
+            tokio::time::sleep(delay).await;
+            delay = std::cmp::min(delay * 2, RECONNECT_MAX_DELAY);
+        }
+    }
+
+    /// log in again with the cached token, the server forgets it with the socket.
+    async fn resume(&self) -> Result<()> {
+        #[derive(Serialize, Debug)]
+        struct ReqBody {
+            token: String,
+        }
+        let token = self.token.lock().unwrap().clone();
+        if let Some(token) = token {
+            let _: serde_json::Value = self
+                .call(
+                    EventType::TokenLoginRequest,
+                    "",
+                    &ReqBody { token },
+                    self.timeout,
+                )
+                .await?;
+            tracing::info!("session resumed.");
+        }
+        Ok(())
+    }
+
+    async fn wait_online(&self) {
+        let mut online = self.online.clone();
+        while !*online.borrow() {
+            if online.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn keep_alive(&self) {
         let req = Request {
             event_type: EventType::KeepAlive,
             request_type: EventType::KeepAlive,
@@ -54,14 +207,17 @@ impl Connenction {
             request_body: "".to_string(),
         };
         loop {
+            self.wait_online().await;
             tracing::trace!("keep alive: {:?}", req);
-            self.send(&req).await?;
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            if let Err(e) = self.send(req.clone()).await {
+                tracing::debug!("keep alive failed: {}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum EventType {
     TokenLoginRequest,
@@ -75,56 +231,152 @@ pub enum EventType {
     OtaConfigurePublish,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl EventType {
+    /// whether sending the request twice is harmless, so it can be retried after a reconnect.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            EventType::TokenLoginRequest
+                | EventType::LoginRequest
+                | EventType::KeepAlive
+                | EventType::OtaFetchVehicleTemplateTable
+                | EventType::OtaFetchVehicleTemplateItemContents
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
-struct Request {
+pub struct Request {
     pub event_type: EventType,
     pub request_type: EventType,
     pub path_parameter: String,
     pub request_body: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
-struct Response {
-    event_type: EventType,
-    error_code: i32,
-    message: Option<String>,
-    data: Option<serde_json::Value>,
+pub struct Response {
+    pub event_type: EventType,
+    pub error_code: i32,
+    pub message: Option<String>,
+    pub data: Option<serde_json::Value>,
 }
 
 impl Connenction {
-    async fn send(&self, req: &Request) -> Result<()> {
-        let msg = Message::Text(serde_json::to_string(req)?);
-        self.tx.unbounded_send(msg)?;
-        Ok(())
+    async fn send(&self, req: Request) -> Result<()> {
+        self.tx
+            .read()
+            .unwrap()
+            .unbounded_send(req)
+            .map_err(|_| OtaError::Transport("disconnected".to_string()))
     }
 
-    async fn recv(&self) -> Result<serde_json::Value> {
-        let mut rx = self.rx.write().await;
-        let resp: Response = serde_json::from_str(
-            &rx.next()
-                .await
-                .ok_or(anyhow::anyhow!("no message"))?
-                .to_string(),
-        )?;
+    /// route a frame received on `link` to the waiter of the same event type.
+    /// the envelope carries no request id, so the server must answer each event
+    /// type in order, and `call` keeps only one request per type in flight.
+    /// keep alive replies and frames nobody waits for are dropped.
+    fn dispatch(&self, link: u64, resp: Response) {
+        if resp.event_type == EventType::KeepAlive {
+            tracing::trace!("keep alive resp: {:?}", resp);
+            return;
+        }
+        let waiter = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.link != link {
+                tracing::debug!("closed link, drop resp: {:?}", resp);
+                return;
+            }
+            pending.waiters.remove(&resp.event_type)
+        };
+        match waiter {
+            Some(waiter) => {
+                if let Err(resp) = waiter.send(resp) {
+                    tracing::warn!("requester gone, drop resp: {:?}", resp);
+                }
+            }
+            None => tracing::debug!("unsolicited, drop resp: {:?}", resp),
+        }
+    }
+
+    fn unpack(resp: Response) -> Result<serde_json::Value> {
         tracing::debug!("recv: {:?}", resp);
         if resp.error_code != 0 {
             tracing::error!("error: {:?}", resp);
-            return Err(anyhow::anyhow!(resp
-                .message
-                .unwrap_or("no error message".to_string())));
+            return Err(OtaError::from_code(resp.error_code, resp.message));
         }
-        if resp.data.is_none() {
-            tracing::error!("no data recv: {:?}", resp);
-            return Err(anyhow::anyhow!("no data"));
+        match resp.data {
+            Some(data) => Ok(data),
+            None => {
+                tracing::error!("no data recv: {:?}", resp);
+                Err(OtaError::EmptyData(resp.event_type))
+            }
         }
-        Ok(resp.data.unwrap())
     }
 }
 
 impl Connenction {
-    pub async fn request<'de, T, D>(&self, event_type: EventType, path: &str, body: &T) -> Result<D>
+    /// send a request and wait for its reply with the default timeout.
+    pub async fn request<T, D>(&self, event_type: EventType, path: &str, body: &T) -> Result<D>
+    where
+        T: Serialize + std::fmt::Debug,
+        D: DeserializeOwned + std::fmt::Debug,
+    {
+        self.request_with_timeout(event_type, path, body, self.timeout)
+            .await
+    }
+
+    /// like `request`, a `OtaError::Timeout` is returned if no reply comes within `timeout`.
+    pub async fn request_with_timeout<T, D>(
+        &self,
+        event_type: EventType,
+        path: &str,
+        body: &T,
+        timeout: Duration,
+    ) -> Result<D>
+    where
+        T: Serialize + std::fmt::Debug,
+        D: DeserializeOwned + std::fmt::Debug,
+    {
+        loop {
+            if tokio::time::timeout(timeout, self.wait_online())
+                .await
+                .is_err()
+            {
+                return Err(OtaError::Timeout {
+                    event_type,
+                    after: timeout,
+                });
+            }
+            match self.call(event_type, path, body, timeout).await {
+                Err(OtaError::Transport(e)) => {
+                    if !event_type.is_idempotent() {
+                        tracing::error!(
+                            "connection lost during {:?}, it may or may not be applied",
+                            event_type
+                        );
+                        return Err(OtaError::Transport(format!(
+                            "{:?} interrupted by {}, check the server state before retrying",
+                            event_type, e
+                        )));
+                    }
+                    tracing::warn!(
+                        "connection lost during {:?}, retry after reconnect",
+                        event_type
+                    );
+                }
+                other => return other,
+            }
+        }
+    }
+
+    async fn call<T, D>(
+        &self,
+        event_type: EventType,
+        path: &str,
+        body: &T,
+        timeout: Duration,
+    ) -> Result<D>
     where
         T: Serialize + std::fmt::Debug,
         D: DeserializeOwned + std::fmt::Debug,
@@ -135,11 +387,66 @@ impl Connenction {
             path_parameter: path.to_string(),
             request_body: serde_json::to_string(body)?,
         };
+        // wait for the previous request of this type, its reply is not told apart
+        let turn = self
+            .turns
+            .lock()
+            .unwrap()
+            .entry(event_type)
+            .or_default()
+            .clone();
+        let _turn = turn.lock().await;
         tracing::debug!("request with req: {:?}", req);
-        self.send(&req).await?;
-        let value = self.recv().await?;
+        let (tx, reply) = oneshot::channel();
+        let link = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.waiters.insert(event_type, tx);
+            pending.link
+        };
+        self.send(req).await?;
+        // from here on, giving up on the reply closes the link
+        let mut in_flight = InFlight {
+            conn: self,
+            event_type,
+            link: Some(link),
+        };
+        let resp = match tokio::time::timeout(timeout, reply).await {
+            Ok(resp) => {
+                in_flight.link = None;
+                resp.map_err(|_| OtaError::Transport("disconnected".to_string()))?
+            }
+            Err(_) => {
+                tracing::error!("{:?} got no reply in {:?}", event_type, timeout);
+                return Err(OtaError::Timeout {
+                    event_type,
+                    after: timeout,
+                });
+            }
+        };
+        let value = Connenction::unpack(resp)?;
         let resp: D = serde_json::from_value(value)?;
         tracing::debug!("request with resp: {:?}", resp);
         Ok(resp)
     }
 }
+
+/// a sent request, dropped before its reply came when it timed out or the
+/// caller dropped the future. the late reply would answer the next request
+/// of the same type, so the link is closed.
+struct InFlight<'a> {
+    conn: &'a Connenction,
+    event_type: EventType,
+    link: Option<u64>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(link) = self.link {
+            tracing::warn!(
+                "{:?} given up before its reply, reconnecting",
+                self.event_type
+            );
+            self.conn.drop_link(link);
+        }
+    }
+}
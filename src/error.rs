@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use crate::connection::EventType;
+
+/// the server's code for an expired session is not documented, so these codes
+/// and errors mentioning one of `AUTH_WORDS` count as expired,
+/// `OtaClient::set_expired_code` adds another code.
+const AUTH_CODES: &[i32] = &[401, 403];
+const AUTH_WORDS: &[&str] = &[
+    "token",
+    "session",
+    "expired",
+    "unauthorized",
+    "not logged in",
+];
+
+pub type Result<T> = std::result::Result<T, OtaError>;
+
+#[derive(Debug, Clone)]
+pub enum OtaError {
+    /// the websocket could not be opened or went away.
+    Transport(String),
+    /// a frame or payload could not be encoded or decoded.
+    Protocol(String),
+    /// the server answered with a non zero error code.
+    Server { code: i32, message: String },
+    /// the server answered without data.
+    EmptyData(EventType),
+    /// the server did not reply in time.
+    Timeout {
+        event_type: EventType,
+        after: Duration,
+    },
+    /// the session token was rejected, log in again.
+    AuthExpired(String),
+    /// the server answered a save with `ok: false`, holds its message.
+    SaveRejected(String),
+    /// a yaml text does not parse, `text` is kept for repair.
+    InvalidYaml {
+        text: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl OtaError {
+    pub fn invalid_yaml(text: &str, e: serde_yaml::Error) -> Self {
+        let (line, column) = e
+            .location()
+            .map(|l| (l.line(), l.column()))
+            .unwrap_or((0, 0));
+        OtaError::InvalidYaml {
+            text: text.to_string(),
+            line,
+            column,
+            message: e.to_string(),
+        }
+    }
+
+    /// turn a non zero error code of a response into an error.
+    pub fn from_code(code: i32, message: Option<String>) -> Self {
+        let message = message.unwrap_or_else(|| "no error message".to_string());
+        let lower = message.to_lowercase();
+        if AUTH_CODES.contains(&code) || AUTH_WORDS.iter().any(|w| lower.contains(w)) {
+            OtaError::AuthExpired(message)
+        } else {
+            OtaError::Server { code, message }
+        }
+    }
+}
+
+impl std::fmt::Display for OtaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OtaError::Transport(e) => write!(f, "transport error: {}", e),
+            OtaError::Protocol(e) => write!(f, "protocol error: {}", e),
+            OtaError::Server { code, message } => write!(f, "server error {}: {}", code, message),
+            OtaError::EmptyData(event_type) => write!(f, "no data in {:?} reply", event_type),
+            OtaError::Timeout { event_type, after } => {
+                write!(f, "{:?} timed out after {:?}", event_type, after)
+            }
+            OtaError::AuthExpired(e) => write!(f, "auth expired: {}", e),
+            OtaError::SaveRejected(e) => write!(f, "save rejected: {}", e),
+            OtaError::InvalidYaml {
+                line,
+                column,
+                message,
+                ..
+            } => write!(f, "invalid yaml at {}:{}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for OtaError {}
+
+impl From<serde_json::Error> for OtaError {
+    fn from(e: serde_json::Error) -> Self {
+        OtaError::Protocol(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for OtaError {
+    fn from(e: serde_yaml::Error) -> Self {
+        OtaError::Protocol(e.to_string())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for OtaError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        OtaError::Transport(e.to_string())
+    }
+}
+
+impl From<native_tls::Error> for OtaError {
+    fn from(e: native_tls::Error) -> Self {
+        OtaError::Transport(e.to_string())
+    }
+}
@@ -0,0 +1,102 @@
+//! append-only record of every save, the old text makes it undoable.
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::local;
+
+/// one saved vehicle config.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// the invocation that saved it, see `run_id`.
+    pub run: String,
+    pub at: chrono::DateTime<chrono::Local>,
+    pub user_id: i32,
+    pub vehicle_id: i32,
+    pub vehicle_name: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// id of this invocation, saves of one run are rolled back together.
+pub fn run_id() -> String {
+    format!(
+        "{}-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    )
+}
+
+fn path() -> Result<PathBuf> {
+    Ok(local::cache_dir()?.join("journal.jsonl"))
+}
+
+pub fn append(entry: &Entry) -> Result<()> {
+    let path = path()?;
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// all entries, oldest first. a missing journal is empty.
+pub fn read() -> Result<Vec<Entry>> {
+    let path = path()?;
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(anyhow::anyhow!("read {:?} failed: {}", path, e)),
+    };
+    let mut entries = vec![];
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!("skip broken journal line: {}", e),
+        }
+    }
+    Ok(entries)
+}
+
+/// what to undo per vehicle: all its saves in `run`, or else its last save.
+/// `old` is the text to restore, `new` the one expected on the server now.
+pub fn targets(entries: &[Entry], run: Option<&str>, vehicles: &[String]) -> Result<Vec<Entry>> {
+    let mut targets: Vec<Entry> = vec![];
+    match run {
+        Some(run) => {
+            for entry in entries.iter().filter(|e| e.run == run) {
+                match targets
+                    .iter_mut()
+                    .find(|t| t.vehicle_id == entry.vehicle_id)
+                {
+                    Some(target) => target.new = entry.new.clone(),
+                    None => targets.push(entry.clone()),
+                }
+            }
+            if targets.is_empty() {
+                return Err(anyhow::anyhow!("no saves in run {}", run));
+            }
+        }
+        None => {
+            for name in vehicles {
+                let entry = entries
+                    .iter()
+                    .rev()
+                    .find(|e| &e.vehicle_name == name)
+                    .ok_or_else(|| anyhow::anyhow!("no journaled save of {}", name))?;
+                targets.push(entry.clone());
+            }
+        }
+    }
+    Ok(targets)
+}
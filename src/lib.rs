@@ -1,31 +1,101 @@
-use std::{collections::HashMap, io::Write};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use cli::{Cli, Command, ConnArgs, ProcessArgs, ProfilesCommand, SelectArgs, VehiclesCommand};
 use colored::*;
-use connection::{Connenction, EventType};
-use serde::{Deserialize, Serialize};
+use futures::{stream, StreamExt};
+use report::Status;
+use transport::{TlsOptions, Transport};
 
+pub mod api;
+mod audit;
 mod auth;
 mod carside;
+pub mod cassette;
+pub mod cli;
+mod client;
 mod connection;
+mod error;
+mod journal;
+mod local;
+#[cfg(feature = "test-support")]
+pub mod mock;
+mod pool;
+mod profile;
+mod report;
+pub mod transport;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Vehicle {
-    id: i32,
-    name: String,
+pub use api::Vehicle;
+pub use carside::PushType;
+pub use client::OtaClient;
+pub use connection::EventType;
+pub use error::OtaError;
+pub use mode::Document;
+
+/// the old and new document of a vehicle.
+type Edit<'a> = (&'a Vehicle, (Document, Document));
+
+/// failed vehicles of a run so far, see `--on-failure`.
+#[derive(Default)]
+struct Failures {
+    /// the error the run ends with.
+    first: Option<anyhow::Error>,
+    /// no further vehicle is started.
+    stopped: bool,
 }
+
+impl Failures {
+    /// the summary entry of a failed vehicle, asks whether to go on.
+    fn record(&mut self, ota: &Ota, vehicle: &Vehicle, e: anyhow::Error) -> report::Entry {
+        tracing::error!("{} failed: {:#}", vehicle.name, e);
+        let entry = report::Entry {
+            error: Some(format!("{:#}", e)),
+            ..report::Entry::new(vehicle, Status::Failed)
+        };
+        self.first.get_or_insert(e);
+        self.stopped = !ota.keep_going();
+        entry
+    }
+
+    fn into_result(self) -> Result<()> {
+        match self.first {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// the interactive and scripted flows of the command line, see `OtaClient` for the api.
 pub struct Ota {
-    user_id: i32,
-    conn: Connenction,
+    /// one logged in client per job.
+    clients: pool::Pool,
     vehicles: Vec<Vehicle>,
+    /// vehicles handled at the same time.
+    jobs: usize,
+    /// journal id of this invocation.
+    run: String,
+    /// saves journaled in this run.
+    saves: AtomicUsize,
+    /// machine readable summary of `process` and `publish_all`.
+    output: cli::Output,
+    output_file: Option<std::path::PathBuf>,
+    on_failure: cli::OnFailure,
+    /// extra tries of a failed fetch or save.
+    retries: u32,
+    /// reply timeout, also how long a save that timed out may still land.
+    timeout: std::time::Duration,
+    /// default of `--push-type`, from the profile.
+    push_type: Option<PushType>,
 }
 
 impl Ota {
     fn get_vehicles(vehicles: &[Vehicle]) -> Result<Vec<Vehicle>> {
         let vehicle_names: Vec<String> = vehicles.iter().map(|v| v.name.clone()).collect();
         let validator: inquire::validator::MultiOptionValidator<String> = &|v| {
-            if v.len() == 0 {
+            if v.is_empty() {
                 Err(String::from("no vehicle selected"))
             } else {
                 Ok(())
@@ -39,119 +109,184 @@ impl Ota {
         Ok(selected.iter().map(|name| map[name].clone()).collect())
     }
 
-    async fn select_vehicle(&mut self) -> Result<()> {
-        #[derive(Serialize, Deserialize, Debug)]
-        #[serde(rename_all = "camelCase")]
-        struct RequestBody {
-            user_id: i32,
-            station_id: String,
-            name: String,
-        }
-        #[derive(Serialize, Deserialize, Debug)]
-        #[serde(rename_all = "camelCase")]
-        struct ResponseBody {
-            total: i32,
-            list: Vec<Vehicle>,
-        }
+    async fn fetch_vehicles(&self) -> error::Result<Vec<Vehicle>> {
+        self.client().await.list_vehicles().await
+    }
 
-        let req = RequestBody {
-            user_id: self.user_id,
-            station_id: "".to_string(),
-            name: "".to_string(),
+    async fn select_vehicle(&mut self, args: &SelectArgs) -> Result<()> {
+        let vehicles = self.fetch_vehicles().await?;
+        self.vehicles = if args.all {
+            vehicles
+        } else if !args.vehicles.is_empty() {
+            args.vehicles
+                .iter()
+                .map(|name| {
+                    vehicles
+                        .iter()
+                        .find(|v| &v.name == name)
+                        .cloned()
+                        .ok_or_else(|| anyhow::anyhow!("no vehicle named {}", name))
+                })
+                .collect::<Result<_>>()?
+        } else {
+            cli::or_prompt(None, "--vehicle", || Ota::get_vehicles(&vehicles))?
         };
-        let resp: ResponseBody = self
-            .conn
-            .request(
-                EventType::OtaFetchVehicleTemplateTable,
-                "?pageSize=10000",
-                &req,
-            )
-            .await?;
-        tracing::info!(
-            "get {} vehicles from ota, total {}",
-            resp.list.len(),
-            resp.total
-        );
-        self.vehicles = Ota::get_vehicles(resp.list.as_slice())?;
         Ok(())
     }
 }
 
 mod mode;
 impl Ota {
-    async fn get_yaml(&self, vehicle: &Vehicle) -> Result<serde_yaml::Value> {
-        #[derive(Debug, Deserialize, Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct RequsetBody {
-            vehicle_id: i32,
+    /// an idle client for one request.
+    async fn client(&self) -> pool::Lease<'_> {
+        self.clients.lease().await
+    }
+
+    /// one client per job for the next `count` vehicles.
+    async fn open_jobs(&self, count: usize) {
+        self.clients.grow(self.jobs.min(count)).await;
+    }
+
+    /// the yaml text of `vehicle` as stored on the server.
+    async fn get_raw_yaml(&self, vehicle: &Vehicle) -> error::Result<String> {
+        self.client().await.fetch_template(vehicle.id).await
+    }
+
+    /// an empty template is an empty document, a broken one an `InvalidYaml` error.
+    async fn get_yaml(&self, vehicle: &Vehicle) -> error::Result<Document> {
+        let str = self.get_raw_yaml(vehicle).await?;
+        if str.trim().is_empty() {
+            tracing::info!("template of {} is empty", vehicle.name);
         }
-        let req = RequsetBody {
-            vehicle_id: vehicle.id,
-        };
-        let str: serde_json::Value = self
-            .conn
-            .request(EventType::OtaFetchVehicleTemplateItemContents, "", &req)
-            .await?;
-        assert!(str.is_string());
-        let yaml: serde_yaml::Value = serde_yaml::from_str(str.as_str().unwrap_or("")).unwrap_or(
-            serde_yaml::Value::String("this is an empty yaml.".to_string()),
-        );
+        let yaml = Document::parse(str.clone()).map_err(|e| OtaError::invalid_yaml(&str, e))?;
         tracing::trace!("get {} yaml: {:?}", vehicle.name, yaml);
         Ok(yaml)
     }
 
-    async fn save(
+    /// let the user fix a yaml that does not parse, `None` if they give up.
+    fn repair(vehicle: &Vehicle, text: &str, error: &OtaError) -> Result<Option<Document>> {
+        let mut text = text.to_string();
+        let mut error = error.to_string();
+        loop {
+            tracing::error!("yaml of {} is broken: {}", vehicle.name, error);
+            if !inquire::Confirm::new(&format!("repair {} in editor", vehicle.name))
+                .with_default(true)
+                .prompt()?
+            {
+                return Ok(None);
+            }
+            text = inquire::Editor::new(&format!("repair {} yaml", vehicle.name))
+                .with_editor_command(std::ffi::OsStr::new("vim"))
+                .with_predefined_text(&text)
+                .with_file_extension(".yaml")
+                .prompt()?;
+            match Document::parse(text.clone()) {
+                Ok(doc) => return Ok(Some(doc)),
+                Err(e) => error = OtaError::invalid_yaml(&text, e).to_string(),
+            }
+        }
+    }
+
+    async fn save(&self, old: &Document, new: &Document, vehicle: &Vehicle) -> error::Result<()> {
+        // never overwrite a config with something the server can't read back
+        if let Err(e) = serde_yaml::from_str::<serde_yaml::Value>(new.text()) {
+            return Err(OtaError::invalid_yaml(new.text(), e));
+        }
+        let resp = self
+            .client()
+            .await
+            .save_template(vehicle.id, old.text(), new.text())
+            .await
+            .inspect_err(|e| {
+                self.audit_save(vehicle, old.text(), new.text(), false, Some(e.to_string()))
+            })?;
+        self.audit_save(
+            vehicle,
+            old.text(),
+            new.text(),
+            resp.ok,
+            resp.message.clone(),
+        );
+        if !resp.ok {
+            let message = resp
+                .message
+                .unwrap_or_else(|| "no error message".to_string());
+            return Err(OtaError::SaveRejected(message));
+        }
+        tracing::info!("save {} success", vehicle.name);
+        self.journal(vehicle, old.text(), new.text());
+        Ok(())
+    }
+
+    /// audit a save with the diff operations between the texts.
+    fn audit_save(
         &self,
-        old: &serde_yaml::Value,
-        new: &serde_yaml::Value,
         vehicle: &Vehicle,
-    ) -> Result<()> {
-        #[derive(Debug, Serialize, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct RequsetBody {
-            user_id: i32,
-            vehicle_id: i32,
-            old_config: String,
-            new_config: String,
-        }
-        #[derive(Debug, Serialize, Deserialize)]
-        #[serde(rename_all = "camelCase")]
-        struct ResponseBody {
-            ok: bool,
-            message: Option<String>,
-        }
-        let req = RequsetBody {
-            user_id: self.user_id,
+        old: &str,
+        new: &str,
+        ok: bool,
+        message: Option<String>,
+    ) {
+        let parse = |text| serde_yaml::from_str::<serde_yaml::Value>(text).unwrap_or_default();
+        let changes = mode::changes(&parse(old), &parse(new)).unwrap_or_default();
+        self.audit(
+            vehicle,
+            audit::Event::Save {
+                changes,
+                ok,
+                message,
+            },
+        );
+    }
+
+    /// append to the audit log, a failed write only warns.
+    pub(crate) fn audit(&self, vehicle: &Vehicle, event: audit::Event) {
+        let record = audit::Record {
+            at: chrono::Local::now(),
+            user_id: self.clients.main().user_id(),
+            host: self.clients.main().endpoint().to_string(),
             vehicle_id: vehicle.id,
-            old_config: serde_yaml::to_string(old)?,
-            new_config: serde_yaml::to_string(new)?,
+            vehicle_name: vehicle.name.clone(),
+            event,
         };
-        let resp: ResponseBody = self
-            .conn
-            .request(EventType::OtaAddVehicleTemplateItem, "", &req)
-            .await?;
-        if resp.ok {
-            tracing::info!("save {} success", vehicle.name);
-        } else {
-            tracing::error!("save {} failed: {:?}", vehicle.name, resp.message);
+        if let Err(e) = audit::append(&record) {
+            tracing::warn!("audit of {} failed: {}", vehicle.name, e);
+        }
+    }
+
+    /// record a save for `rollback`, a failed write only warns as the save is done.
+    fn journal(&self, vehicle: &Vehicle, old: &str, new: &str) {
+        let entry = journal::Entry {
+            run: self.run.clone(),
+            at: chrono::Local::now(),
+            user_id: self.clients.main().user_id(),
+            vehicle_id: vehicle.id,
+            vehicle_name: vehicle.name.clone(),
+            old: old.to_string(),
+            new: new.to_string(),
+        };
+        match journal::append(&entry) {
+            Ok(()) => {
+                self.saves.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => tracing::warn!("journal save of {} failed: {}", vehicle.name, e),
         }
-        Ok(())
     }
 
-    fn preview_confirm(old: &serde_yaml::Value, new: &serde_yaml::Value) -> Result<bool> {
+    fn preview_confirm(old: &Document, new: &Document) -> Result<bool> {
         let mut file = tempfile::Builder::new()
             .prefix("temp-preview")
             .suffix(".yaml")
             .tempfile()?;
         let path = file.path().to_owned();
-        file.write_all(serde_yaml::to_string(old)?.as_bytes())?;
+        file.write_all(old.text().as_bytes())?;
         file.flush()?;
 
         inquire::Editor::new("preview")
             .with_help_message("[use :qa to quit]")
             .with_editor_command(std::ffi::OsStr::new("vimdiff"))
             .with_file_extension(".yaml")
-            .with_predefined_text(serde_yaml::to_string(new)?.as_str())
+            .with_predefined_text(new.text())
             .with_args(&[
                 std::ffi::OsStr::new("-c"),
                 std::ffi::OsStr::new("set readonly wrap"),
@@ -173,66 +308,935 @@ impl Ota {
             .map_err(|e| anyhow::anyhow!(e))
     }
 
-    async fn process(&mut self) -> Result<()> {
-        let manual = Ota::get_manual()?;
-        let mut carside = carside::Carside::new()?;
+    /// the server copy of `vehicle` as old and new document, a broken one
+    /// is offered for repair. `None` if it stays broken, an error without a terminal.
+    fn checkout(
+        vehicle: &Vehicle,
+        yaml: error::Result<Document>,
+    ) -> Result<Option<(Document, Document)>> {
+        match yaml {
+            Ok(old) => Ok(Some((old.clone(), old))),
+            Err(OtaError::InvalidYaml {
+                text,
+                line,
+                column,
+                message,
+            }) => {
+                let error = OtaError::InvalidYaml {
+                    text: String::new(),
+                    line,
+                    column,
+                    message,
+                };
+                // nobody to repair it, fail the vehicle so ci notices
+                if !cli::interactive() {
+                    return Err(error.into());
+                }
+                match Ota::repair(vehicle, &text, &error)? {
+                    Some(repaired) => Ok(Some((Document::unparsed(text), repaired))),
+                    None => {
+                        tracing::warn!("skip broken {}", vehicle.name);
+                        Ok(None)
+                    }
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        let mut modified = vec![];
-        let mut skipped = vec![];
-        let mut handle_map = HashMap::new();
+    /// run `handles` once each in order on every vehicle,
+    /// without handles the modes are asked for per vehicle.
+    async fn process(
+        &mut self,
+        mut handles: Vec<Box<dyn mode::YamlHandle>>,
+        args: &ProcessArgs,
+    ) -> Result<()> {
+        // a plan is confirmed as a whole instead of per vehicle
+        let manual = !args.plan && cli::or_prompt(args.manual, "--manual", Ota::get_manual)?;
+        if manual && !cli::interactive() {
+            return Err(anyhow::anyhow!("manual confirm needs a terminal"));
+        }
+        if handles.is_empty() && !cli::interactive() {
+            return Err(anyhow::anyhow!("--mode is required without a terminal"));
+        }
+        let carside = carside::Carside::new(&cli::PublishArgs {
+            auto_publish: args.publish.auto_publish,
+            push_type: args.publish.push_type.or(self.push_type),
+        })?;
+        let mut failures = Failures::default();
+        if args.plan || (!manual && !handles.is_empty()) {
+            let (edits, mut entries) = self.plan(handles, &mut failures).await?;
+            if failures.stopped {
+                // nothing is saved after an abort while planning
+                entries.extend(
+                    edits
+                        .iter()
+                        .map(|(v, _)| report::Entry::new(v, Status::NotReached)),
+                );
+                self.summary(&entries)?;
+                return failures.into_result();
+            }
+            if args.plan && !Ota::confirm_plan(&edits, args.yes)? {
+                tracing::warn!("plan discarded, nothing saved.");
+                return failures.into_result();
+            }
+            return self
+                .commit(edits, entries, failures, &carside, args.rollback)
+                .await;
+        }
 
+        let mut entries = vec![];
+        let mut handle_map = HashMap::new();
         for v in &self.vehicles {
+            if failures.stopped {
+                entries.push(report::Entry::new(v, Status::NotReached));
+                continue;
+            }
             tracing::info!("start process {}.", v.name);
-            let old = self.get_yaml(v).await?;
-            let mut new = old.clone();
+            let entry = match self
+                .process_vehicle(v, &mut handles, &mut handle_map, manual, &carside)
+                .await
+            {
+                Ok(entry) => entry,
+                Err(e) => failures.record(self, v, e),
+            };
+            entries.push(entry);
+        }
+        tracing::info!("process done.");
+        self.summary(&entries)?;
+        failures.into_result()
+    }
+
+    /// fetch, edit, save and publish one vehicle of the one by one flow.
+    async fn process_vehicle(
+        &self,
+        v: &Vehicle,
+        handles: &mut [Box<dyn mode::YamlHandle>],
+        handle_map: &mut HashMap<mode::Mode, Box<dyn mode::YamlHandle>>,
+        manual: bool,
+        carside: &carside::Carside,
+    ) -> Result<report::Entry> {
+        let yaml = self.retry(v, || self.get_yaml(v)).await;
+        let Some((old, new)) = self.edit(v, yaml, handles, handle_map)? else {
+            return Ok(report::Entry::new(v, Status::Skipped));
+        };
+        if Ota::same(&old, &new) {
+            return Ok(Ota::unchanged(v));
+        }
+        let changes = Ota::changes(&old, &new);
+        if manual && !Ota::preview_confirm(&old, &new)? {
+            tracing::warn!("skip {}", v.name);
+            return Ok(report::Entry::new(v, Status::Skipped));
+        }
+        let published = self.save_and_publish(v, &old, &new, carside).await?;
+        Ok(report::Entry {
+            changes: Some(changes),
+            published,
+            ..report::Entry::new(v, Status::Modified)
+        })
+    }
+
+    /// check out a fetched yaml and run the modes on it, `None` if skipped.
+    fn edit(
+        &self,
+        v: &Vehicle,
+        yaml: error::Result<Document>,
+        handles: &mut [Box<dyn mode::YamlHandle>],
+        handle_map: &mut HashMap<mode::Mode, Box<dyn mode::YamlHandle>>,
+    ) -> Result<Option<(Document, Document)>> {
+        let Some((old, mut new)) = Ota::checkout(v, yaml)? else {
+            return Ok(None);
+        };
+        if handles.is_empty() {
             while let Some(mode) = mode::get_handle_mode()? {
-                let handle = handle_map.entry(mode).or_insert(mode::get_handle(&mode));
-                new = handle.handle(self, &v, &new)?;
+                let handle = handle_map
+                    .entry(mode)
+                    .or_insert_with(|| mode::get_handle(&mode));
+                new = handle.handle(self, v, &new)?;
+            }
+        } else {
+            for handle in handles.iter_mut() {
+                new = handle.handle(self, v, &new)?;
+            }
+        }
+        Ok(Some((old, new)))
+    }
+
+    /// save, then publish as `carside` says. a failed save is not published,
+    /// a failed publish is not retried, it may have gone through.
+    async fn save_and_publish(
+        &self,
+        v: &Vehicle,
+        old: &Document,
+        new: &Document,
+        carside: &carside::Carside,
+    ) -> error::Result<Option<report::Published>> {
+        self.retry_save(v, old, new).await?;
+        carside.process(self, v).await
+    }
+
+    /// like `retry` for `save`, but only while the server still holds `old`,
+    /// since a save that timed out or lost its connection may have landed.
+    async fn retry_save(&self, v: &Vehicle, old: &Document, new: &Document) -> error::Result<()> {
+        let mut tried = 0;
+        loop {
+            let e = match self.save(old, new, v).await {
+                Err(e) if tried < self.retries && !matches!(e, OtaError::InvalidYaml { .. }) => e,
+                result => return result,
+            };
+            if matches!(e, OtaError::Timeout { .. } | OtaError::Transport(_)) {
+                // give a late save the time to land before looking
+                tokio::time::sleep(self.timeout).await;
+            }
+            match self.get_raw_yaml(v).await {
+                Ok(text) if text == old.text() => {}
+                Ok(_) => {
+                    tracing::error!("{} changed on the server, save not retried", v.name);
+                    return Err(e);
+                }
+                Err(check) => {
+                    tracing::error!("check {} failed: {}, save not retried", v.name, check);
+                    return Err(e);
+                }
             }
-            if !manual || Ota::preview_confirm(&old, &new)? {
-                self.save(&old, &new, v).await?;
-                carside.process(self, v).await?;
-                modified.push(v.name.clone());
+            tried += 1;
+            tracing::warn!("{} failed: {}, retry {}/{}", v.name, e, tried, self.retries);
+        }
+    }
+
+    /// run `f` up to `--retries` more times while it fails.
+    /// a yaml that does not parse won't parse on the next try either.
+    async fn retry<T, F, Fut>(&self, v: &Vehicle, f: F) -> error::Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = error::Result<T>>,
+    {
+        let mut tried = 0;
+        loop {
+            match f().await {
+                Err(e) if tried < self.retries && !matches!(e, OtaError::InvalidYaml { .. }) => {
+                    tried += 1;
+                    tracing::warn!("{} failed: {}, retry {}/{}", v.name, e, tried, self.retries);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// after a failure, whether to go on with the remaining vehicles.
+    fn keep_going(&self) -> bool {
+        match self.on_failure {
+            cli::OnFailure::Abort => false,
+            cli::OnFailure::Continue => true,
+            cli::OnFailure::Ask => {
+                cli::interactive()
+                    && inquire::Confirm::new("continue with the remaining vehicles")
+                        .with_default(false)
+                        .prompt()
+                        .unwrap_or(false)
+            }
+        }
+    }
+
+    /// fetch every vehicle, `jobs` at a time, and edit it in memory.
+    /// returns the old and new document per vehicle and the entries of the others.
+    async fn plan(
+        &self,
+        mut handles: Vec<Box<dyn mode::YamlHandle>>,
+        failures: &mut Failures,
+    ) -> Result<(Vec<Edit<'_>>, Vec<report::Entry>)> {
+        self.open_jobs(self.vehicles.len()).await;
+        let fetched: Vec<_> = stream::iter(&self.vehicles)
+            .map(|v| self.retry(v, || self.get_yaml(v)))
+            .buffered(self.jobs)
+            .collect()
+            .await;
+
+        let mut entries = vec![];
+        let mut edits = vec![];
+        let mut handle_map = HashMap::new();
+        for (v, yaml) in self.vehicles.iter().zip(fetched) {
+            if failures.stopped {
+                entries.push(report::Entry::new(v, Status::NotReached));
+                continue;
+            }
+            if handles.is_empty() {
+                tracing::info!("plan {}.", v.name);
+            }
+            match self.edit(v, yaml, &mut handles, &mut handle_map) {
+                Ok(Some(docs)) => edits.push((v, docs)),
+                Ok(None) => entries.push(report::Entry::new(v, Status::Skipped)),
+                Err(e) => entries.push(failures.record(self, v, e)),
+            }
+        }
+        Ok((edits, entries))
+    }
+
+    /// print the diff of every planned edit and ask once for all of them.
+    fn confirm_plan(edits: &[Edit], yes: bool) -> Result<bool> {
+        let mut count = 0;
+        for (v, (old, new)) in edits {
+            if Ota::same(old, new) {
+                println!("{}", format!("= {} (no change)", v.name).bold());
+            } else {
+                Ota::print_diff(&v.name, old.text(), new.text(), "planned");
+                count += 1;
+            }
+        }
+        // unchanged vehicles are left alone, nothing to confirm
+        if yes || count == 0 {
+            return Ok(true);
+        }
+        if !cli::interactive() {
+            return Err(anyhow::anyhow!(
+                "--yes is required to commit a plan without a terminal"
+            ));
+        }
+        inquire::Confirm::new(&format!("save {} vehicles", count))
+            .with_default(false)
+            .prompt()
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// save and publish the planned edits, `jobs` vehicles at a time.
+    /// with `rollback` a failure restores the vehicles saved so far.
+    async fn commit(
+        &self,
+        edits: Vec<Edit<'_>>,
+        mut untouched: Vec<report::Entry>,
+        failures: Failures,
+        carside: &carside::Carside,
+        rollback: bool,
+    ) -> Result<()> {
+        let mut changes = vec![];
+        let mut changed = vec![];
+        for (v, (old, new)) in edits {
+            if Ota::same(&old, &new) {
+                untouched.push(Ota::unchanged(v));
             } else {
-                tracing::warn!("skip {}", v.name);
-                skipped.push(v.name.clone());
+                changes.push(Some(Ota::changes(&old, &new)));
+                changed.push((v, (old, new)));
             }
         }
+        let edits = changed;
+        let texts: Vec<_> = edits
+            .iter()
+            .map(|(v, (old, new))| (*v, old.text().to_string(), new.text().to_string()))
+            .collect();
+        let results = self
+            .for_each_vehicle(edits, |v, (old, new)| async move {
+                self.save_and_publish(v, &old, &new, carside).await
+            })
+            .await;
+        let mut rolled_back = vec![];
+        if rollback && results.iter().any(|(_, r)| matches!(r, Some(Err(_)))) {
+            let started = texts
+                .iter()
+                .zip(&results)
+                .filter(|(_, (_, r))| r.is_some())
+                .map(|(t, _)| t);
+            rolled_back = self.roll_back(started).await;
+        }
+        self.finish(results, changes, untouched, &rolled_back, failures)
+    }
+
+    /// put back the old text of vehicles whose server copy is still what this run saved.
+    /// published configs stay published. returns the ids of the restored vehicles.
+    async fn roll_back<'a>(
+        &self,
+        saved: impl Iterator<Item = &'a (&'a Vehicle, String, String)>,
+    ) -> Vec<i32> {
+        let mut restored = vec![];
+        for (v, old, new) in saved {
+            if old == new {
+                continue;
+            }
+            match self.get_raw_yaml(v).await {
+                Ok(text) if text == *new => {}
+                Ok(_) => {
+                    tracing::info!("{} not saved by this run, leave it", v.name);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("roll back {} failed: {}", v.name, e);
+                    continue;
+                }
+            }
+            let saved = self.client().await.save_template(v.id, new, old).await;
+            match saved {
+                Ok(resp) if resp.ok => {
+                    tracing::warn!("rolled back {}", v.name);
+                    self.audit_save(v, new, old, true, None);
+                    self.journal(v, new, old);
+                    restored.push(v.id);
+                }
+                Ok(resp) => {
+                    tracing::error!("roll back {} failed: {:?}", v.name, resp.message);
+                    self.audit_save(v, new, old, false, resp.message);
+                }
+                Err(e) => {
+                    tracing::error!("roll back {} failed: {}", v.name, e);
+                    self.audit_save(v, new, old, false, Some(e.to_string()));
+                }
+            }
+        }
+        restored
+    }
+
+    /// run `f` for each vehicle, `jobs` at a time, logging progress.
+    /// no vehicle is started after one failed, those come back as `None`.
+    async fn for_each_vehicle<'a, T, R, F, Fut>(
+        &self,
+        items: Vec<(&'a Vehicle, T)>,
+        f: F,
+    ) -> Vec<(&'a Vehicle, Option<error::Result<R>>)>
+    where
+        F: Fn(&'a Vehicle, T) -> Fut,
+        Fut: std::future::Future<Output = error::Result<R>>,
+    {
+        let total = items.len();
+        self.open_jobs(total).await;
+        let done = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let (done, stop, f) = (&done, &stop, &f);
+        let mut results: Vec<_> = stream::iter(items.into_iter().enumerate())
+            .map(|(i, (v, item))| async move {
+                if stop.load(Ordering::Relaxed) {
+                    return (i, v, None);
+                }
+                let result = f(v, item).await;
+                let n = done.fetch_add(1, Ordering::Relaxed) + 1;
+                match &result {
+                    Ok(_) => tracing::info!("[{}/{}] {} done", n, total, v.name),
+                    Err(e) => {
+                        tracing::error!("[{}/{}] {} failed: {}", n, total, v.name, e);
+                        if !self.keep_going() {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+                (i, v, Some(result))
+            })
+            .buffer_unordered(self.jobs)
+            .collect()
+            .await;
+        results.sort_by_key(|(i, _, _)| *i);
+        results.into_iter().map(|(_, v, r)| (v, r)).collect()
+    }
+
+    /// print the summary of `for_each_vehicle`, the first failure is returned.
+    /// `changes` counts the edited paths per result, `None` if nothing was edited.
+    /// `untouched` are the vehicles not handed to `for_each_vehicle`,
+    /// `failures` those that failed before.
+    fn finish(
+        &self,
+        results: Vec<(&Vehicle, Option<error::Result<Option<report::Published>>>)>,
+        changes: Vec<Option<usize>>,
+        untouched: Vec<report::Entry>,
+        rolled_back: &[i32],
+        mut failures: Failures,
+    ) -> Result<()> {
+        let mut entries = vec![];
+        for ((v, result), changes) in results.into_iter().zip(changes) {
+            let entry = match result {
+                Some(Ok(published)) => {
+                    let status = if rolled_back.contains(&v.id) {
+                        Status::RolledBack
+                    } else {
+                        Status::Modified
+                    };
+                    report::Entry {
+                        published,
+                        ..report::Entry::new(v, status)
+                    }
+                }
+                Some(Err(e)) => {
+                    let entry = report::Entry {
+                        error: Some(e.to_string()),
+                        ..report::Entry::new(v, Status::Failed)
+                    };
+                    failures.first.get_or_insert(e.into());
+                    entry
+                }
+                None => report::Entry::new(v, Status::NotReached),
+            };
+            entries.push(report::Entry { changes, ..entry });
+        }
+        entries.extend(untouched);
         tracing::info!("process done.");
+        self.summary(&entries)?;
+        failures.into_result()
+    }
+
+    /// changed yaml paths of an edit.
+    fn changes(old: &Document, new: &Document) -> usize {
+        mode::changes(old.value(), new.value()).map_or(0, |c| c.len())
+    }
+
+    /// whether an edit changed nothing, neither a value nor the text, e.g. a comment.
+    fn same(old: &Document, new: &Document) -> bool {
+        old.text() == new.text() && Ota::changes(old, new) == 0
+    }
+
+    /// an edit without changes is neither saved nor published.
+    fn unchanged(vehicle: &Vehicle) -> report::Entry {
+        tracing::info!("{} unchanged, not saved", vehicle.name);
+        report::Entry {
+            changes: Some(0),
+            ..report::Entry::new(vehicle, Status::Unchanged)
+        }
+    }
+
+    /// log the summary and write the `--output` report.
+    fn summary(&self, entries: &[report::Entry]) -> Result<()> {
+        let names = |status| {
+            entries
+                .iter()
+                .filter(|e| e.status == status)
+                .map(|e| e.vehicle_name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
         tracing::info!(
             r#"
 summary:
 modified: [{}]
+unchanged: [{}]
 skipped: [{}]
+failed: [{}]
+not reached: [{}]
+rolled back: [{}]
 "#,
-            modified.join(", ").color("green"),
-            skipped.join(", ").color("red"),
+            names(Status::Modified).color("green"),
+            names(Status::Unchanged),
+            names(Status::Skipped).color("yellow"),
+            names(Status::Failed).color("red"),
+            names(Status::NotReached).color("yellow"),
+            names(Status::RolledBack).color("yellow"),
         );
-        Ok(())
+        if self.saves.load(Ordering::Relaxed) > 0 {
+            tracing::info!("undo this run with `ota-yaml rollback --run {}`", self.run);
+        }
+        report::Report {
+            run: &self.run,
+            vehicles: entries,
+        }
+        .write(self.output, self.output_file.as_deref())
     }
 }
 
 impl Ota {
     fn get_host() -> Result<String> {
         inquire::Text::new("ota host")
+            .with_help_message("host name or full ws:// / wss:// url")
             .with_default("ota.zelostech.com.cn")
             .prompt()
             .map_err(|e| anyhow::anyhow!(e))
     }
 
-    pub async fn run() -> Result<()> {
-        let host = Ota::get_host()?;
-        let port = 8090;
-        let path = "/user_client";
-        let mut ota = Ota {
-            user_id: -1,
-            conn: Connenction::new(&host, port, path).await?,
+    /// `--endpoint` wins, otherwise a host or a full url.
+    fn get_endpoint(args: &ConnArgs) -> Result<String> {
+        if let Some(endpoint) = &args.endpoint {
+            return Ok(endpoint.clone());
+        }
+        let host = cli::or_prompt(args.host.clone(), "--host", Ota::get_host)?;
+        Ok(Ota::endpoint_url(&host))
+    }
+
+    /// a bare host means the default plain endpoint of that host.
+    fn endpoint_url(host: &str) -> String {
+        if host.contains("://") {
+            host.to_string()
+        } else {
+            format!("ws://{}:8090/user_client", host)
+        }
+    }
+
+    fn reply_timeout(args: &ConnArgs) -> std::time::Duration {
+        args.timeout
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(connection::DEFAULT_TIMEOUT)
+    }
+
+    async fn connect(args: &ConnArgs) -> Result<OtaClient> {
+        let endpoint = Ota::get_endpoint(args)?;
+        let tls = TlsOptions {
+            ca_file: args.ca_file.clone(),
+            insecure: args.insecure,
+        };
+        let timeout = Ota::reply_timeout(args);
+        let mut transport: Arc<dyn Transport> =
+            Arc::new(transport::WebSocket::new(&endpoint, &tls)?);
+        if let Some(path) = &args.record {
+            transport = Arc::new(cassette::Recording::new(transport, path)?);
+        }
+        let mut client = OtaClient::with_transport(transport, timeout).await?;
+        if let Some(code) = args.expired_code {
+            client.set_expired_code(code);
+        }
+        Ok(client)
+    }
+
+    /// a run with the logged in `client`, `timeout` as in `connect`.
+    fn new(client: OtaClient, timeout: std::time::Duration) -> Ota {
+        Ota {
+            clients: pool::Pool::new(client),
+            jobs: 1,
+            run: journal::run_id(),
+            saves: AtomicUsize::new(0),
+            output: cli::Output::Text,
+            output_file: None,
+            on_failure: cli::OnFailure::Ask,
+            retries: 0,
+            timeout,
+            push_type: None,
             vehicles: vec![],
+        }
+    }
+
+    async fn print_yaml(&self) -> Result<()> {
+        for v in &self.vehicles {
+            // raw text, a broken yaml is still worth looking at
+            let text = self.get_raw_yaml(v).await?;
+            println!("# {}\n{}\n---", v.name, text.trim_end());
+        }
+        Ok(())
+    }
+
+    async fn download_yaml(&self, dir: &std::path::Path, metadata: bool) -> Result<()> {
+        for v in &self.vehicles {
+            let text = self.get_raw_yaml(v).await?;
+            let path = local::write(dir, v, &text, metadata)?;
+            tracing::info!("saved {} to {:?}", v.name, path);
+        }
+        Ok(())
+    }
+
+    fn print_diff(name: &str, old: &str, new: &str, label: &str) {
+        let diff = similar::TextDiff::from_lines(old, new);
+        println!(
+            "{}",
+            format!("--- {} (server)\n+++ {} ({})", name, name, label).bold()
+        );
+        for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+            for line in hunk.to_string().lines() {
+                match line.chars().next() {
+                    Some('+') => println!("{}", line.green()),
+                    Some('-') => println!("{}", line.red()),
+                    Some('@') => println!("{}", line.cyan()),
+                    _ => println!("{}", line),
+                }
+            }
+        }
+    }
+
+    fn get_conflict(name: &str) -> Result<cli::Conflict> {
+        use cli::Conflict;
+        if !cli::interactive() {
+            return Ok(Conflict::Refuse);
+        }
+        inquire::Select::new(
+            &format!("{} changed on the server since download", name),
+            vec![Conflict::Refuse, Conflict::Merge, Conflict::Overwrite],
+        )
+        .prompt()
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// the yaml to upload for `file`, `None` if it must not be uploaded.
+    fn resolve_conflict(
+        file: &local::LocalFile,
+        server_text: &str,
+        server: &Document,
+        local: Document,
+        on_conflict: Option<cli::Conflict>,
+    ) -> Result<Option<Document>> {
+        let meta = match &file.metadata {
+            Some(meta) => meta,
+            None => {
+                tracing::warn!("no metadata for {:?}, can't detect conflicts", file.path);
+                return Ok(Some(local));
+            }
         };
+        if meta.sha256 == local::hash(server_text) {
+            return Ok(Some(local));
+        }
+        let on_conflict = match on_conflict {
+            Some(c) => c,
+            None => Ota::get_conflict(&file.name)?,
+        };
+        match on_conflict {
+            cli::Conflict::Refuse => {
+                tracing::error!(
+                    "{} changed on the server since {}, refuse to apply {:?}",
+                    file.name,
+                    meta.fetched_at,
+                    file.path
+                );
+                Ok(None)
+            }
+            cli::Conflict::Overwrite => {
+                tracing::warn!("overwrite newer server copy of {}", file.name);
+                Ok(Some(local))
+            }
+            cli::Conflict::Merge => {
+                let base = match &meta.base {
+                    Some(base) => serde_yaml::from_str(base)?,
+                    None => {
+                        tracing::error!("no base in metadata of {}, can't merge", file.name);
+                        return Ok(None);
+                    }
+                };
+                match mode::merge(&base, local.value(), server.value()) {
+                    // patched onto the server text, its formatting is kept
+                    Ok(merged) => Ok(Some(server.update(merged)?)),
+                    Err(e) => {
+                        tracing::error!("merge {} failed: {}", file.name, e);
+                        Ok(None)
+                    }
+                }
+            }
+        }
+    }
+
+    /// upload the local files that differ from their server copy.
+    async fn apply(&mut self, args: &cli::ApplyArgs) -> Result<()> {
+        let files = local::collect(&args.paths)?;
+        self.select_vehicle(&SelectArgs {
+            vehicles: files.iter().map(|f| f.name.clone()).collect(),
+            all: false,
+        })
+        .await?;
+
+        let mut prepared = HashMap::new();
+        let mut changed = vec![];
+        let mut refused = vec![];
+        let mut uploads = vec![];
+        for (v, file) in self.vehicles.iter().zip(files.iter()) {
+            let server_text = self.get_raw_yaml(v).await?;
+            let server = Document::parse(server_text.clone())
+                .map_err(|e| OtaError::invalid_yaml(&server_text, e))?;
+            let local = Document::parse(file.text.clone())
+                .map_err(|e| OtaError::invalid_yaml(&file.text, e))
+                .with_context(|| format!("parse {:?} failed", file.path))?;
+            let new = match Ota::resolve_conflict(
+                file,
+                &server_text,
+                &server,
+                local,
+                args.on_conflict,
+            )? {
+                Some(new) => new,
+                None => {
+                    refused.push(v.name.clone());
+                    continue;
+                }
+            };
+            if Ota::same(&server, &new) {
+                tracing::info!("{} unchanged, skip", v.name);
+                continue;
+            }
+            Ota::print_diff(&v.name, server.text(), new.text(), "local");
+            if file.metadata.is_some() {
+                uploads.push((v.clone(), file.path.clone(), new.text().to_string()));
+            }
+            prepared.insert(v.name.clone(), new);
+            changed.push(v.clone());
+        }
+        let result = if changed.is_empty() {
+            tracing::info!("nothing to apply.");
+            Ok(())
+        } else {
+            self.vehicles = changed;
+            self.process(vec![mode::file_handle(prepared)], &args.process)
+                .await
+        };
+        self.update_sidecars(&uploads).await;
+        result?;
+        if !refused.is_empty() {
+            return Err(anyhow::anyhow!("refused to apply [{}]", refused.join(", ")));
+        }
+        Ok(())
+    }
+
+    /// point the sidecars of uploaded files at the server text, so the next
+    /// `apply` doesn't take its own save for a change on the server.
+    /// a merged file gets the merged text.
+    async fn update_sidecars(&self, uploads: &[(Vehicle, std::path::PathBuf, String)]) {
+        for (v, path, text) in uploads {
+            match self.get_raw_yaml(v).await {
+                Ok(server) if server == *text => {
+                    if let Err(e) = local::write_file(path, v, text, true) {
+                        tracing::warn!("update {:?} failed: {}", path, e);
+                    }
+                }
+                // not saved, e.g. declined or failed
+                Ok(_) => {}
+                Err(e) => tracing::warn!("check {} failed: {}, {:?} not updated", v.name, e, path),
+            }
+        }
+    }
 
-        ota.user_id = auth::auth(&ota).await?;
-        ota.select_vehicle().await?;
-        ota.process().await?;
+    async fn rollback(&mut self, args: &cli::RollbackArgs) -> Result<()> {
+        let entries = journal::read()?;
+        if args.list {
+            let mut runs: Vec<(&journal::Entry, Vec<&str>)> = vec![];
+            for entry in &entries {
+                match runs.iter_mut().find(|(first, _)| first.run == entry.run) {
+                    Some((_, names)) => names.push(&entry.vehicle_name),
+                    None => runs.push((entry, vec![&entry.vehicle_name])),
+                }
+            }
+            for (first, names) in runs {
+                println!(
+                    "{}\t{}\tuser {}\t{}",
+                    first.run,
+                    first.at.format("%Y-%m-%d %H:%M:%S"),
+                    first.user_id,
+                    names.join(",")
+                );
+            }
+            return Ok(());
+        }
+        if args.run.is_none() && args.vehicles.is_empty() {
+            return Err(anyhow::anyhow!("--run, --vehicle or --list is required"));
+        }
+        let targets = journal::targets(&entries, args.run.as_deref(), &args.vehicles)?;
+        self.select_vehicle(&SelectArgs {
+            vehicles: targets.iter().map(|t| t.vehicle_name.clone()).collect(),
+            all: false,
+        })
+        .await?;
+
+        let mut prepared = HashMap::new();
+        let mut changed = vec![];
+        let mut refused = vec![];
+        for (v, target) in self.vehicles.iter().zip(&targets) {
+            let server = self.get_raw_yaml(v).await?;
+            if server == target.old {
+                tracing::info!("{} already restored, skip", v.name);
+                continue;
+            }
+            if server != target.new && !args.force {
+                tracing::warn!("{} changed after the journaled save, use --force", v.name);
+                refused.push(v.name.clone());
+                continue;
+            }
+            let old = Document::parse(target.old.clone())
+                .map_err(|e| OtaError::invalid_yaml(&target.old, e))
+                .with_context(|| format!("journaled config of {} is broken", v.name))?;
+            prepared.insert(v.name.clone(), old);
+            changed.push(v.clone());
+        }
+        if !refused.is_empty() {
+            tracing::warn!("refused: [{}]", refused.join(", ").color("red"));
+        }
+        if changed.is_empty() {
+            tracing::info!("nothing to roll back.");
+            return Ok(());
+        }
+        self.vehicles = changed;
+        self.process(vec![mode::file_handle(prepared)], &args.process)
+            .await
+    }
+
+    async fn publish_all(&self, args: &cli::PublishArgs) -> Result<()> {
+        let carside = carside::Carside::new(args)?;
+        let vehicles = self.vehicles.iter().map(|v| (v, ())).collect();
+        let results = self
+            .for_each_vehicle(vehicles, |v, ()| carside.process(self, v))
+            .await;
+        let changes = vec![None; results.len()];
+        self.finish(results, changes, vec![], &[], Failures::default())
+    }
+
+    pub async fn run(mut cli: Cli) -> Result<()> {
+        // local commands, no need to log in
+        match &cli.command {
+            Some(Command::Audit(args)) => return audit::query(args),
+            Some(Command::Profiles {
+                command: ProfilesCommand::List,
+            }) => {
+                profile::load()?.list();
+                return Ok(());
+            }
+            _ => {}
+        }
+        let profile = profile::load()?.select(cli.profile.as_deref())?;
+        if let Some((name, profile)) = &profile {
+            tracing::info!("use profile {}", name);
+            profile.apply(&mut cli);
+        }
+        let mut client = Ota::connect(&cli.conn).await?;
+        let profile_name = profile.as_ref().map(|(name, _)| name.as_str());
+        auth::auth(&mut client, &cli.login, profile_name).await?;
+        let mut ota = Ota::new(client, Ota::reply_timeout(&cli.conn));
+        ota.jobs = cli.jobs.into();
+        ota.output = cli.output;
+        ota.output_file = cli.output_file;
+        ota.on_failure = cli.on_failure;
+        ota.retries = cli.retries;
+        ota.push_type = profile.as_ref().and_then(|(_, p)| p.push_type);
+
+        match cli.command {
+            None => {
+                ota.select_vehicle(&SelectArgs::default()).await?;
+                ota.process(vec![], &ProcessArgs::default()).await?;
+            }
+            Some(Command::Login) => {
+                tracing::info!("logged in as user {}", ota.clients.main().user_id())
+            }
+            Some(Command::Vehicles {
+                command: VehiclesCommand::List,
+            }) => {
+                for v in ota.fetch_vehicles().await? {
+                    println!("{}\t{}", v.id, v.name);
+                }
+            }
+            Some(Command::Get(args)) => {
+                ota.select_vehicle(&args.select).await?;
+                match &args.output_dir {
+                    Some(dir) => ota.download_yaml(dir, args.metadata).await?,
+                    None => ota.print_yaml().await?,
+                }
+            }
+            Some(Command::Edit(args)) => {
+                ota.select_vehicle(&args.select).await?;
+                let handles = mode::get_handles(&args.modes, args.components.as_deref());
+                ota.process(handles, &args.process).await?;
+            }
+            Some(Command::Set(args)) => {
+                ota.select_vehicle(&args.select).await?;
+                let handles = vec![mode::set_handle(&args.assignments)?];
+                ota.process(handles, &args.process).await?;
+            }
+            Some(Command::Apply(args)) => ota.apply(&args).await?,
+            Some(Command::Rollback(args)) => ota.rollback(&args).await?,
+            Some(Command::Publish(args)) => {
+                ota.select_vehicle(&args).await?;
+                ota.publish_all(&cli::PublishArgs {
+                    auto_publish: Some(true),
+                    push_type: Some(carside::PushType::None),
+                })
+                .await?;
+            }
+            Some(Command::Push(args)) => {
+                ota.select_vehicle(&args.select).await?;
+                let push_type = cli::or_prompt(
+                    args.push_type.or(ota.push_type),
+                    "--push-type",
+                    carside::get_push_type,
+                )?;
+                ota.publish_all(&cli::PublishArgs {
+                    auto_publish: Some(true),
+                    push_type: Some(push_type),
+                })
+                .await?;
+            }
+            Some(Command::Audit(_) | Command::Profiles { .. }) => {
+                unreachable!("handled before connecting")
+            }
+        }
 
         Ok(())
     }
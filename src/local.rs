@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Vehicle;
+
+/// sidecar of a downloaded `<vehicle>.yaml`, records which server copy it came from.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    pub vehicle_id: i32,
+    pub vehicle_name: String,
+    pub fetched_at: chrono::DateTime<chrono::Local>,
+    /// sha256 of the server yaml text at fetch time.
+    pub sha256: String,
+    /// the server yaml text at fetch time, the base of a three-way merge.
+    #[serde(default)]
+    pub base: Option<String>,
+}
+
+/// a `<vehicle>.yaml` to upload, with its sidecar if there is one.
+#[derive(Debug)]
+pub struct LocalFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub text: String,
+    pub metadata: Option<Metadata>,
+}
+
+impl LocalFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow::anyhow!("can't get vehicle name of {:?}", path))?
+            .to_string();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("read {:?} failed: {}", path, e))?;
+        let meta_path = metadata_path(path);
+        let metadata = if meta_path.exists() {
+            let meta: Metadata = serde_json::from_str(&std::fs::read_to_string(&meta_path)?)
+                .map_err(|e| anyhow::anyhow!("parse {:?} failed: {}", meta_path, e))?;
+            Some(meta)
+        } else {
+            None
+        };
+        Ok(LocalFile {
+            name: metadata
+                .as_ref()
+                .map(|m| m.vehicle_name.clone())
+                .unwrap_or(name),
+            path: path.to_path_buf(),
+            text,
+            metadata,
+        })
+    }
+}
+
+/// read the given files, a directory stands for all `*.yaml` in it.
+pub fn collect(paths: &[PathBuf]) -> Result<Vec<LocalFile>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort();
+            for entry in entries {
+                if entry.extension().map(|e| e == "yaml").unwrap_or(false) {
+                    files.push(LocalFile::read(&entry)?);
+                }
+            }
+        } else {
+            files.push(LocalFile::read(path)?);
+        }
+    }
+    Ok(files)
+}
+
+pub fn hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// `<dir>/<vehicle>.yaml`, a `/` in the name would escape the directory.
+pub fn yaml_path(dir: &Path, vehicle_name: &str) -> PathBuf {
+    dir.join(format!("{}.yaml", vehicle_name.replace('/', "_")))
+}
+
+pub fn metadata_path(yaml_path: &Path) -> PathBuf {
+    yaml_path.with_extension("meta.json")
+}
+
+/// write the server text of `vehicle` into `dir`, with a sidecar if `metadata`.
+pub fn write(dir: &Path, vehicle: &Vehicle, text: &str, metadata: bool) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = yaml_path(dir, &vehicle.name);
+    write_file(&path, vehicle, text, metadata)?;
+    Ok(path)
+}
+
+/// write the server text of `vehicle` to `path`, e.g. again after uploading it.
+pub fn write_file(path: &Path, vehicle: &Vehicle, text: &str, metadata: bool) -> Result<()> {
+    std::fs::write(path, text)?;
+    if metadata {
+        let meta = Metadata {
+            vehicle_id: vehicle.id,
+            vehicle_name: vehicle.name.clone(),
+            fetched_at: chrono::Local::now(),
+            sha256: hash(text),
+            base: Some(text.to_string()),
+        };
+        std::fs::write(metadata_path(path), serde_json::to_string_pretty(&meta)?)?;
+    }
+    Ok(())
+}
+
+/// `~/.cache/ota-yaml`, where the token and the journals live.
+pub fn cache_dir() -> Result<PathBuf> {
+    let user_dir = directories::UserDirs::new().ok_or(anyhow::anyhow!("can't find home dir"))?;
+    Ok(user_dir.home_dir().join(".cache/ota-yaml"))
+}
+
+/// `~/.config/ota-yaml`, where the profiles live.
+pub fn config_dir() -> Result<PathBuf> {
+    let user_dir = directories::UserDirs::new().ok_or(anyhow::anyhow!("can't find home dir"))?;
+    Ok(user_dir.home_dir().join(".config/ota-yaml"))
+}
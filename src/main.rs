@@ -1,9 +1,17 @@
 use anyhow::Result;
+use clap::Parser;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    ota_yaml::Ota::run().await.map_err(|e| {
+    // stdout is kept for command output
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+    let cli = ota_yaml::cli::Cli::parse();
+    ota_yaml::Ota::run(cli).await.map_err(|e| {
+        if let Some(ota_yaml::OtaError::AuthExpired(_)) = e.downcast_ref() {
+            tracing::error!("session expired during the run, please run again to log in");
+        }
         tracing::error!("ota yaml run failed: {}", e);
         e
     })
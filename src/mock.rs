@@ -0,0 +1,493 @@
+//! an in-memory ota server speaking the websocket envelope, for offline tests.
+//!
+//! every `EventType` is served from a vehicle/template store, and faults can
+//! be queued per event type to exercise error codes, slow replies and drops.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::connection::{EventType, Request, Response};
+
+/// error code of replies to an unknown or expired session.
+const SESSION_ERROR_CODE: i32 = 1;
+
+/// what goes wrong with the next request of an event type.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// reply with a non zero error code.
+    Code(i32, String),
+    /// reply after `Duration`.
+    Delay(Duration),
+    /// close the connection without a reply.
+    Drop,
+    /// reply without data.
+    Empty,
+    /// reply `{"ok": false, "message": ..}` without handling the request.
+    Refuse(String),
+}
+
+/// how login replies tell when the token expires, tokens are never expired by time.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TokenExpiry {
+    /// an opaque token and no expiry.
+    #[default]
+    Unknown,
+    /// an `expiresIn` field with the seconds.
+    ExpiresIn(Duration),
+    /// an unsigned jwt with an `exp` claim.
+    Jwt(Duration),
+}
+
+/// a config saved with `OtaAddVehicleTemplateItem`.
+#[derive(Debug, Clone)]
+pub struct Save {
+    pub user_id: i32,
+    pub vehicle_id: i32,
+    pub old_config: String,
+    pub new_config: String,
+}
+
+/// a config published with `OtaAddConfigurePublish`.
+#[derive(Debug, Clone)]
+pub struct Publish {
+    pub id: i32,
+    pub vehicle_id: i32,
+    pub modify_user_id: i32,
+    pub approved: bool,
+    /// the `commandType` it was pushed with.
+    pub pushed: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: i32,
+    /// username to password and user id.
+    users: HashMap<String, (String, i32)>,
+    /// username to the organizations it may log into, any if none.
+    organizations: HashMap<String, Vec<(i32, String)>>,
+    /// token to user id.
+    tokens: HashMap<String, i32>,
+    token_expiry: TokenExpiry,
+    vehicles: Vec<(i32, String)>,
+    templates: HashMap<i32, String>,
+    saves: Vec<Save>,
+    publishes: Vec<Publish>,
+    faults: HashMap<EventType, VecDeque<Fault>>,
+    requests: Vec<EventType>,
+    connections: Vec<JoinHandle<()>>,
+}
+
+impl State {
+    fn id(&mut self) -> i32 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+/// a running mock server, stopped on drop.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// listen on a free local port.
+    pub async fn start() -> std::io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let s = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let conn = tokio::spawn(serve(s.clone(), stream));
+                s.lock().unwrap().connections.push(conn);
+            }
+        });
+        Ok(MockServer { addr, state, task })
+    }
+
+    /// the endpoint to pass as `--endpoint`.
+    pub fn url(&self) -> String {
+        format!("ws://{}/user_client", self.addr)
+    }
+
+    /// add a user able to log in, returns its id.
+    pub fn add_user(&self, username: &str, password: &str) -> i32 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.id();
+        state
+            .users
+            .insert(username.to_string(), (password.to_string(), id));
+        id
+    }
+
+    /// let `username` log into this organization, once it has one no other.
+    pub fn add_organization(&self, username: &str, id: i32, name: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .organizations
+            .entry(username.to_string())
+            .or_default()
+            .push((id, name.to_string()));
+    }
+
+    /// add a vehicle with its template text, returns its id.
+    pub fn add_vehicle(&self, name: &str, yaml: &str) -> i32 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.id();
+        state.vehicles.push((id, name.to_string()));
+        state.templates.insert(id, yaml.to_string());
+        id
+    }
+
+    /// the current template of a vehicle.
+    pub fn template(&self, vehicle_id: i32) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .templates
+            .get(&vehicle_id)
+            .cloned()
+    }
+
+    /// change a template behind the client's back.
+    pub fn set_template(&self, vehicle_id: i32, yaml: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .templates
+            .insert(vehicle_id, yaml.to_string());
+    }
+
+    pub fn saves(&self) -> Vec<Save> {
+        self.state.lock().unwrap().saves.clone()
+    }
+
+    pub fn publishes(&self) -> Vec<Publish> {
+        self.state.lock().unwrap().publishes.clone()
+    }
+
+    /// event types of all requests received so far, keep alives excluded.
+    pub fn requests(&self) -> Vec<EventType> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// queue a fault for the next request of `event_type`.
+    pub fn inject(&self, event_type: EventType, fault: Fault) {
+        self.state
+            .lock()
+            .unwrap()
+            .faults
+            .entry(event_type)
+            .or_default()
+            .push_back(fault);
+    }
+
+    pub fn set_token_expiry(&self, expiry: TokenExpiry) {
+        self.state.lock().unwrap().token_expiry = expiry;
+    }
+
+    /// forget all issued tokens, as if they expired.
+    pub fn expire_tokens(&self) {
+        self.state.lock().unwrap().tokens.clear();
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        for conn in self.state.lock().unwrap().connections.drain(..) {
+            conn.abort();
+        }
+    }
+}
+
+async fn serve(state: Arc<Mutex<State>>, stream: TcpStream) {
+    let ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            tracing::warn!("mock accept failed: {}", e);
+            return;
+        }
+    };
+    let (mut write, mut read) = ws.split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Response>();
+    let writer = tokio::spawn(async move {
+        while let Some(resp) = rx.recv().await {
+            let text = serde_json::to_string(&resp).unwrap();
+            if write.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+    // like the real server, a login only holds for its socket
+    let session = Arc::new(Mutex::new(None::<i32>));
+
+    while let Some(Ok(msg)) = read.next().await {
+        let req: Request = match msg {
+            Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(req) => req,
+                Err(e) => {
+                    tracing::warn!("mock got bad request {}: {}", text, e);
+                    continue;
+                }
+            },
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let fault = {
+            let mut state = state.lock().unwrap();
+            if req.event_type != EventType::KeepAlive {
+                state.requests.push(req.event_type);
+            }
+            state
+                .faults
+                .get_mut(&req.event_type)
+                .and_then(|faults| faults.pop_front())
+        };
+        let event_type = req.event_type;
+        match fault {
+            None => {
+                let _ = tx.send(reply(event_type, handle(&state, &session, &req)));
+            }
+            Some(Fault::Code(code, message)) => {
+                let _ = tx.send(reply(event_type, Err((code, message))));
+            }
+            Some(Fault::Empty) => {
+                let _ = tx.send(reply(event_type, Ok(None)));
+            }
+            Some(Fault::Refuse(message)) => {
+                let data = json!({ "ok": false, "message": message });
+                let _ = tx.send(reply(event_type, Ok(Some(data))));
+            }
+            Some(Fault::Delay(delay)) => {
+                let (state, session, tx) = (state.clone(), session.clone(), tx.clone());
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let _ = tx.send(reply(event_type, handle(&state, &session, &req)));
+                });
+            }
+            Some(Fault::Drop) => break,
+        }
+    }
+    writer.abort();
+}
+
+fn reply(event_type: EventType, result: Result<Option<Value>, (i32, String)>) -> Response {
+    match result {
+        Ok(data) => Response {
+            event_type,
+            error_code: 0,
+            message: None,
+            data,
+        },
+        Err((error_code, message)) => Response {
+            event_type,
+            error_code,
+            message: Some(message),
+            data: None,
+        },
+    }
+}
+
+fn body<T: serde::de::DeserializeOwned>(req: &Request) -> Result<T, (i32, String)> {
+    serde_json::from_str(&req.request_body).map_err(|e| (400, format!("bad request body: {}", e)))
+}
+
+fn handle(
+    state: &Mutex<State>,
+    session: &Mutex<Option<i32>>,
+    req: &Request,
+) -> Result<Option<Value>, (i32, String)> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TokenLogin {
+        token: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Login {
+        username: String,
+        password: String,
+        organization_id: i32,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ByVehicle {
+        vehicle_id: i32,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct AddItem {
+        vehicle_id: i32,
+        old_config: String,
+        new_config: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct AddPublish {
+        vehicle_id: i32,
+        modify_user_id: i32,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Approve {
+        id: i32,
+        is_approve: bool,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Push {
+        vehicle_zelos_configure_build_history_id: i32,
+        command_type: Value,
+    }
+
+    let mut state = state.lock().unwrap();
+    let user_id = *session.lock().unwrap();
+    // an unknown session gets a generic code, only its message tells
+    let logged_in = || user_id.ok_or((SESSION_ERROR_CODE, "not logged in".to_string()));
+    match req.event_type {
+        EventType::KeepAlive => Ok(None),
+        EventType::TokenLoginRequest => {
+            let login: TokenLogin = body(req)?;
+            let id = *state
+                .tokens
+                .get(&login.token)
+                .ok_or((SESSION_ERROR_CODE, "token expired".to_string()))?;
+            *session.lock().unwrap() = Some(id);
+            Ok(Some(json!({ "id": id })))
+        }
+        EventType::LoginRequest => {
+            let login: Login = body(req)?;
+            let id = match state.users.get(&login.username) {
+                Some((password, id)) if *password == login.password => *id,
+                _ => return Err((1, "wrong username or password".to_string())),
+            };
+            let organizations = state
+                .organizations
+                .get(&login.username)
+                .cloned()
+                .unwrap_or_default();
+            if !organizations.is_empty()
+                && !organizations
+                    .iter()
+                    .any(|(o, _)| *o == login.organization_id)
+            {
+                return Err((
+                    403,
+                    format!("not in organization {}", login.organization_id),
+                ));
+            }
+            let token = format!("token-{}-{}", id, state.id());
+            let mut reply = match state.token_expiry {
+                TokenExpiry::Unknown => json!({ "id": id, "token": token }),
+                TokenExpiry::ExpiresIn(ttl) => {
+                    json!({ "id": id, "token": token, "expiresIn": ttl.as_secs() })
+                }
+                TokenExpiry::Jwt(ttl) => {
+                    let exp = chrono::Utc::now().timestamp() + ttl.as_secs() as i64;
+                    let claims = json!({ "sub": token, "exp": exp }).to_string();
+                    let encode = |part: &str| base64::encode_config(part, base64::URL_SAFE_NO_PAD);
+                    let jwt = format!("{}.{}.", encode(r#"{"alg":"none"}"#), encode(&claims));
+                    json!({ "id": id, "token": jwt })
+                }
+            };
+            reply["organizations"] = organizations
+                .iter()
+                .map(|(id, name)| json!({ "id": id, "name": name }))
+                .collect();
+            let token = reply["token"].as_str().unwrap().to_string();
+            state.tokens.insert(token, id);
+            *session.lock().unwrap() = Some(id);
+            Ok(Some(reply))
+        }
+        EventType::OtaFetchVehicleTemplateTable => {
+            logged_in()?;
+            let list: Vec<Value> = state
+                .vehicles
+                .iter()
+                .map(|(id, name)| json!({ "id": id, "name": name }))
+                .collect();
+            Ok(Some(json!({ "total": list.len(), "list": list })))
+        }
+        EventType::OtaFetchVehicleTemplateItemContents => {
+            logged_in()?;
+            let by: ByVehicle = body(req)?;
+            let text = state
+                .templates
+                .get(&by.vehicle_id)
+                .ok_or((404, format!("no vehicle {}", by.vehicle_id)))?;
+            Ok(Some(Value::String(text.clone())))
+        }
+        EventType::OtaAddVehicleTemplateItem => {
+            let user_id = logged_in()?;
+            let item: AddItem = body(req)?;
+            if !state.templates.contains_key(&item.vehicle_id) {
+                return Ok(Some(json!({ "ok": false, "message": "no such vehicle" })));
+            }
+            state
+                .templates
+                .insert(item.vehicle_id, item.new_config.clone());
+            state.saves.push(Save {
+                user_id,
+                vehicle_id: item.vehicle_id,
+                old_config: item.old_config,
+                new_config: item.new_config,
+            });
+            Ok(Some(json!({ "ok": true })))
+        }
+        EventType::OtaAddConfigurePublish => {
+            logged_in()?;
+            let publish: AddPublish = body(req)?;
+            let id = state.id();
+            state.publishes.push(Publish {
+                id,
+                vehicle_id: publish.vehicle_id,
+                modify_user_id: publish.modify_user_id,
+                approved: false,
+                pushed: None,
+            });
+            Ok(Some(json!({ "id": id, "vehicleId": publish.vehicle_id })))
+        }
+        EventType::OtaEditConfigurePublish => {
+            logged_in()?;
+            let approve: Approve = body(req)?;
+            let ok = match state.publishes.iter_mut().find(|p| p.id == approve.id) {
+                Some(publish) => {
+                    publish.approved = approve.is_approve;
+                    true
+                }
+                None => false,
+            };
+            Ok(Some(json!({ "ok": ok })))
+        }
+        EventType::OtaConfigurePublish => {
+            logged_in()?;
+            let push: Push = body(req)?;
+            let ok = match state
+                .publishes
+                .iter_mut()
+                .find(|p| p.id == push.vehicle_zelos_configure_build_history_id)
+            {
+                Some(publish) if publish.approved => {
+                    publish.pushed = push.command_type.as_str().map(str::to_string);
+                    true
+                }
+                _ => false,
+            };
+            Ok(Some(json!({ "ok": ok })))
+        }
+    }
+}
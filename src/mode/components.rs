@@ -3,11 +3,23 @@ use std::collections::HashSet;
 
 pub struct Data {
     selected: Option<HashSet<String>>,
+    preset: bool,
 }
 
 impl Data {
     pub fn new() -> Self {
-        Data { selected: None }
+        Data {
+            selected: None,
+            preset: false,
+        }
+    }
+
+    /// enable exactly `components` without asking.
+    pub fn with_selected(components: &[String]) -> Self {
+        Data {
+            selected: Some(components.iter().cloned().collect()),
+            preset: true,
+        }
     }
 }
 
@@ -16,23 +28,25 @@ impl super::YamlHandle for Data {
         &mut self,
         _ota: &crate::Ota,
         _vehicle: &crate::Vehicle,
-        yaml: &serde_yaml::Value,
-    ) -> anyhow::Result<serde_yaml::Value> {
-        let selected: HashSet<String>;
-        let mut yaml = yaml.clone();
-        if self.selected.is_some()
-            && inquire::Confirm::new(&"[components] redo last".color("yellow"))
+        doc: &super::Document,
+    ) -> anyhow::Result<super::Document> {
+        let mut yaml = doc.value().clone();
+        let redo = match &self.selected {
+            Some(_) if self.preset => true,
+            Some(_) => inquire::Confirm::new(&"[components] redo last".color("yellow"))
                 .with_default(true)
-                .prompt()?
-        {
-            selected = self.selected.as_ref().unwrap().clone();
+                .prompt()?,
+            None => false,
+        };
+        let selected: HashSet<String> = if let (true, Some(last)) = (redo, &self.selected) {
+            last.clone()
         } else {
             let mut components = vec![];
             let mut enabled = vec![];
             for xxk in ["xxka", "xxkb"] {
                 for (idx, comp) in yaml[xxk]["archon"]["component"]
                     .as_sequence()
-                    .unwrap()
+                    .ok_or_else(|| anyhow::anyhow!("no {}.archon.component in yaml", xxk))?
                     .iter()
                     .enumerate()
                 {
@@ -42,14 +56,16 @@ impl super::YamlHandle for Data {
                     }
                 }
             }
-            selected = inquire::MultiSelect::new("select enbale components", components)
+            inquire::MultiSelect::new("select enbale components", components)
                 .with_default(&enabled)
                 .prompt()?
                 .into_iter()
-                .collect();
-        }
+                .collect()
+        };
         for xxk in ["xxka", "xxkb"] {
-            let components = yaml[xxk]["archon"]["component"].as_sequence_mut().unwrap();
+            let components = yaml[xxk]["archon"]["component"]
+                .as_sequence_mut()
+                .ok_or_else(|| anyhow::anyhow!("no {}.archon.component in yaml", xxk))?;
             for component in components.iter_mut() {
                 if !selected.contains(component["name"].as_str().unwrap()) {
                     component["enable"] = serde_yaml::Value::Bool(false);
@@ -59,6 +75,6 @@ impl super::YamlHandle for Data {
             }
         }
         self.selected = Some(selected);
-        Ok(yaml)
+        doc.update(yaml)
     }
 }
@@ -0,0 +1,420 @@
+use std::ops::Range;
+
+use anyhow::Result;
+use saphyr_parser::{Event, Parser, ScalarStyle};
+
+use super::edit::{self, Operation, PathKey};
+type Yaml = serde_yaml::Value;
+
+/// a yaml text with its parsed value. updates patch only the changed
+/// scalars, so comments, key order and quoting elsewhere stay byte-identical.
+#[derive(Debug, Clone)]
+pub struct Document {
+    text: String,
+    value: Yaml,
+}
+
+/// source layout of a parsed node, spans are byte ranges of the text.
+enum Node {
+    Scalar {
+        value: String,
+        style: ScalarStyle,
+        span: Range<usize>,
+    },
+    Sequence {
+        flow: bool,
+        items: Vec<Node>,
+    },
+    Mapping {
+        flow: bool,
+        entries: Vec<(Node, Node)>,
+    },
+    Alias(Range<usize>),
+}
+
+impl Node {
+    fn start(&self) -> Option<usize> {
+        match self {
+            Node::Scalar { span, .. } | Node::Alias(span) => Some(span.start),
+            Node::Sequence { items, .. } => items.first()?.start(),
+            Node::Mapping { entries, .. } => entries.first()?.0.start(),
+        }
+    }
+
+    fn end(&self) -> Option<usize> {
+        match self {
+            Node::Scalar { span, .. } | Node::Alias(span) => Some(span.end),
+            Node::Sequence { items, .. } => items.last()?.end(),
+            Node::Mapping { entries, .. } => entries.last()?.1.end(),
+        }
+    }
+}
+
+/// start of the line holding `index`.
+fn line_start(text: &str, index: usize) -> usize {
+    text[..index].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+/// start of the line after the one holding `index`.
+fn next_line(text: &str, index: usize) -> usize {
+    text[index..]
+        .find('\n')
+        .map(|i| index + i + 1)
+        .unwrap_or(text.len())
+}
+
+/// `value` as block lines indented by `indent`.
+fn indented(value: &Yaml, indent: usize) -> Option<String> {
+    let text = serde_yaml::to_string(value).ok()?;
+    Some(
+        text.lines()
+            .map(|line| format!("{}{}\n", " ".repeat(indent), line))
+            .collect(),
+    )
+}
+
+/// text to insert at byte `start`.
+struct Insert {
+    start: usize,
+    text: String,
+}
+
+impl Document {
+    pub fn parse(text: String) -> Result<Self, serde_yaml::Error> {
+        let value = serde_yaml::from_str(&text)?;
+        Ok(Document { text, value })
+    }
+
+    /// keeps a text that does not parse, e.g. the broken server copy
+    /// to send as old config. its value is null.
+    pub fn unparsed(text: String) -> Self {
+        Document {
+            text,
+            value: Yaml::Null,
+        }
+    }
+
+    /// a fresh text for `value`, nothing to preserve.
+    pub fn from_value(value: Yaml) -> Result<Self, serde_yaml::Error> {
+        Ok(Document {
+            text: serde_yaml::to_string(&value)?,
+            value,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn value(&self) -> &Yaml {
+        &self.value
+    }
+
+    /// a document holding `new`, the text of every unchanged node is kept.
+    /// falls back to a fresh text when a change can't be patched in place.
+    pub fn update(&self, new: Yaml) -> Result<Document> {
+        let operations = edit::Data::diff(&self.value, &new)?;
+        if operations.is_empty() {
+            return Ok(self.clone());
+        }
+        match self.patch(&operations) {
+            Some(text) if serde_yaml::from_str::<Yaml>(&text).ok().as_ref() == Some(&new) => {
+                Ok(Document { text, value: new })
+            }
+            _ => {
+                tracing::warn!("can't keep the formatting of this change, rewrite the whole yaml");
+                Ok(Document::from_value(new)?)
+            }
+        }
+    }
+
+    fn patch(&self, operations: &[Operation]) -> Option<String> {
+        let root = Document::layout(&self.text)?;
+        let mut replaces = vec![];
+        for (order, op) in operations.iter().enumerate() {
+            let (span, replace) = match op {
+                Operation::Mod(path, value) => match Document::seek(&root, path)? {
+                    Node::Scalar { style, span, .. } => {
+                        (span.clone(), Document::emit(*style, value)?)
+                    }
+                    _ => return None,
+                },
+                Operation::Add(path, value) => {
+                    let (last, parent) = path.split_last()?;
+                    let at = self.insert(Document::seek(&root, parent)?, last, value)?;
+                    (at.start..at.start, at.text)
+                }
+                Operation::Del(path, _) => {
+                    let (last, parent) = path.split_last()?;
+                    (
+                        self.remove(Document::seek(&root, parent)?, last)?,
+                        String::new(),
+                    )
+                }
+            };
+            // index adds come in reverse, keep them in index order
+            let order = match op.path().last() {
+                Some(PathKey::Index(index)) => *index,
+                _ => order,
+            };
+            replaces.push((span, replace, order));
+        }
+        replaces.sort_by_key(|(span, _, order)| std::cmp::Reverse((span.start, *order)));
+        let mut text = self.text.clone();
+        for (span, replace, _) in replaces {
+            text.replace_range(span, &replace);
+        }
+        Some(text)
+    }
+
+    /// where and what to insert to add `value` under `key` of a block collection.
+    fn insert(&self, parent: &Node, key: &PathKey, value: &Yaml) -> Option<Insert> {
+        let (last, item) = match (parent, key) {
+            (
+                Node::Mapping {
+                    flow: false,
+                    entries,
+                },
+                PathKey::Key(key),
+            ) => {
+                let (last_key, last_value) = entries.last()?;
+                let start = last_key.start()?;
+                let mut mapping = serde_yaml::Mapping::new();
+                mapping.insert(key.clone(), value.clone());
+                let indent = start - line_start(&self.text, start);
+                (last_value, indented(&Yaml::Mapping(mapping), indent)?)
+            }
+            (Node::Sequence { flow: false, items }, PathKey::Index(_) | PathKey::NameIndex(_)) => {
+                let last = items.last()?;
+                let start = last.start()?;
+                let line = line_start(&self.text, start);
+                let dash = line + self.text[line..start].rfind('-')?;
+                let item = Yaml::Sequence(vec![value.clone()]);
+                (last, indented(&item, dash - line)?)
+            }
+            _ => return None,
+        };
+        let end = last.end()?;
+        let at = next_line(&self.text, end.saturating_sub(1).max(last.start()?));
+        let text = if at == self.text.len() && !self.text.ends_with('\n') {
+            format!("\n{}", item)
+        } else {
+            item
+        };
+        Some(Insert { start: at, text })
+    }
+
+    /// the whole lines of the entry or item `key` of a block collection.
+    fn remove(&self, parent: &Node, key: &PathKey) -> Option<Range<usize>> {
+        let (index, starts) = match (parent, key) {
+            (
+                Node::Mapping {
+                    flow: false,
+                    entries,
+                },
+                PathKey::Key(key),
+            ) => {
+                let key = Document::key_text(key)?;
+                let index = entries
+                    .iter()
+                    .position(|(k, _)| matches!(k, Node::Scalar { value, .. } if *value == key))?;
+                let starts = entries
+                    .iter()
+                    .map(|(k, _)| k.start())
+                    .collect::<Option<Vec<_>>>()?;
+                (index, starts)
+            }
+            (Node::Sequence { flow: false, items }, key) => {
+                let index = match key {
+                    PathKey::Index(index) => *index,
+                    PathKey::NameIndex(name) => items
+                        .iter()
+                        .position(|item| Document::name(item) == Some(name.as_str()))?,
+                    PathKey::Key(_) => return None,
+                };
+                let starts = items
+                    .iter()
+                    .map(|item| {
+                        let start = item.start()?;
+                        let line = line_start(&self.text, start);
+                        Some(line + self.text[line..start].rfind('-')?)
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                (index, starts)
+            }
+            _ => return None,
+        };
+        // only whole lines can go, nothing else may share them
+        let own_line = |at: usize| {
+            let line = line_start(&self.text, at);
+            self.text[line..at].trim().is_empty()
+        };
+        let start = *starts.get(index)?;
+        if !own_line(start) {
+            return None;
+        }
+        let end = match starts.get(index + 1) {
+            Some(next) if own_line(*next) => line_start(&self.text, *next),
+            Some(_) => return None,
+            None => {
+                let end = match parent {
+                    Node::Mapping { entries, .. } => entries[index].1.end()?,
+                    Node::Sequence { items, .. } => items[index].end()?,
+                    _ => return None,
+                };
+                next_line(&self.text, end.saturating_sub(1).max(start))
+            }
+        };
+        Some(line_start(&self.text, start)..end)
+    }
+
+    fn key_text(key: &Yaml) -> Option<String> {
+        match key {
+            Yaml::String(key) => Some(key.clone()),
+            key => Some(serde_yaml::to_string(key).ok()?.trim_end().to_string()),
+        }
+    }
+
+    fn layout(text: &str) -> Option<Node> {
+        // the parser counts characters, the spans are byte offsets
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        let byte = |index: usize| offsets.get(index).copied().unwrap_or(text.len());
+        let mut events = Parser::new_from_str(text).map(|event| {
+            event.map(|(event, span)| (event, byte(span.start.index())..byte(span.end.index())))
+        });
+        loop {
+            match events.next()?.ok()? {
+                (Event::StreamStart, _) | (Event::DocumentStart(_), _) => continue,
+                (event, span) => return Document::node(text, event, span, &mut events),
+            }
+        }
+    }
+
+    fn node<'a>(
+        text: &str,
+        event: Event<'a>,
+        span: Range<usize>,
+        events: &mut impl Iterator<Item = Result<(Event<'a>, Range<usize>), saphyr_parser::ScanError>>,
+    ) -> Option<Node> {
+        match event {
+            Event::Scalar(value, style, ..) => Some(Node::Scalar {
+                value: value.to_string(),
+                style,
+                span: Document::closing_quote(text, style, span)?,
+            }),
+            Event::Alias(_) => Some(Node::Alias(span)),
+            Event::SequenceStart(..) => {
+                let flow = text[span.start..].starts_with('[');
+                let mut items = vec![];
+                loop {
+                    match events.next()?.ok()? {
+                        (Event::SequenceEnd, _) => return Some(Node::Sequence { flow, items }),
+                        (event, span) => items.push(Document::node(text, event, span, events)?),
+                    }
+                }
+            }
+            Event::MappingStart(..) => {
+                let flow = text[span.start..].starts_with('{');
+                let mut entries = vec![];
+                loop {
+                    let key = match events.next()?.ok()? {
+                        (Event::MappingEnd, _) => return Some(Node::Mapping { flow, entries }),
+                        (event, span) => Document::node(text, event, span, events)?,
+                    };
+                    let (event, span) = events.next()?.ok()?;
+                    entries.push((key, Document::node(text, event, span, events)?));
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// the span of a quoted scalar ends at its closing quote,
+    /// the parser runs it on over a trailing comment.
+    fn closing_quote(text: &str, style: ScalarStyle, span: Range<usize>) -> Option<Range<usize>> {
+        let quote = match style {
+            ScalarStyle::SingleQuoted => b'\'',
+            ScalarStyle::DoubleQuoted => b'"',
+            _ => return Some(span),
+        };
+        let bytes = text.as_bytes();
+        let mut i = span.start + 1;
+        while i < span.end {
+            match bytes[i] {
+                b'\\' if quote == b'"' => i += 2,
+                b'\'' if quote == b'\'' && bytes.get(i + 1) == Some(&b'\'') => i += 2,
+                c if c == quote => return Some(span.start..i + 1),
+                _ => i += 1,
+            }
+        }
+        None
+    }
+
+    fn entry<'a>(entries: &'a [(Node, Node)], key: &str) -> Option<&'a Node> {
+        entries.iter().find_map(|(k, v)| match k {
+            Node::Scalar { value, .. } if value == key => Some(v),
+            _ => None,
+        })
+    }
+
+    /// the name of a named sequence item, same rule as `edit::Data::name`.
+    fn name(node: &Node) -> Option<&str> {
+        let entries = match node {
+            Node::Mapping { entries, .. } => entries,
+            _ => return None,
+        };
+        ["name", "key"]
+            .iter()
+            .find_map(|key| match Document::entry(entries, key) {
+                Some(Node::Scalar { value, .. }) => Some(value.as_str()),
+                _ => None,
+            })
+    }
+
+    fn seek<'a>(root: &'a Node, path: &[PathKey]) -> Option<&'a Node> {
+        let mut cur = root;
+        for key in path {
+            cur = match (cur, key) {
+                (Node::Mapping { entries, .. }, PathKey::Key(key)) => {
+                    Document::entry(entries, &Document::key_text(key)?)?
+                }
+                (Node::Sequence { items, .. }, PathKey::Index(index)) => items.get(*index)?,
+                (Node::Sequence { items, .. }, PathKey::NameIndex(name)) => items
+                    .iter()
+                    .find(|item| Document::name(item) == Some(name.as_str()))?,
+                _ => return None,
+            }
+        }
+        Some(cur)
+    }
+
+    /// source text of `value` in the style of the scalar it replaces.
+    fn emit(style: ScalarStyle, value: &Yaml) -> Option<String> {
+        if matches!(
+            value,
+            Yaml::Mapping(_) | Yaml::Sequence(_) | Yaml::Tagged(_)
+        ) {
+            return None;
+        }
+        match (style, value) {
+            (ScalarStyle::SingleQuoted, Yaml::String(s)) if !s.contains('\n') => {
+                Some(format!("'{}'", s.replace('\'', "''")))
+            }
+            (ScalarStyle::DoubleQuoted, Yaml::String(s)) => serde_json::to_string(s).ok(),
+            (ScalarStyle::Plain | ScalarStyle::SingleQuoted | ScalarStyle::DoubleQuoted, _) => {
+                let text = serde_yaml::to_string(value).ok()?;
+                let text = text.trim_end_matches('\n');
+                if text.contains('\n') {
+                    None
+                } else {
+                    Some(text.to_string())
+                }
+            }
+            _ => None,
+        }
+    }
+}
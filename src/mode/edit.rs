@@ -2,22 +2,70 @@ use std::hash::{Hash, Hasher};
 
 use anyhow::Result;
 use colored::*;
+
+use super::Document;
 type Yaml = serde_yaml::Value;
 
-#[derive(Debug, Clone)]
-enum PathKey {
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum PathKey {
     Key(Yaml),
     Index(usize),
     NameIndex(String),
 }
 
 #[derive(Debug, Clone)]
-enum Operation {
+pub(super) enum Operation {
     Add(Vec<PathKey>, Yaml),
     Mod(Vec<PathKey>, Yaml),
     Del(Vec<PathKey>, Yaml),
 }
 
+impl Operation {
+    pub(super) fn path(&self) -> &[PathKey] {
+        match self {
+            Operation::Add(path, _) | Operation::Mod(path, _) | Operation::Del(path, _) => path,
+        }
+    }
+
+    pub(super) fn change(&self) -> super::Change {
+        let (op, value) = match self {
+            Operation::Add(_, value) => ("add", value),
+            Operation::Mod(_, value) => ("mod", value),
+            Operation::Del(_, value) => ("del", value),
+        };
+        super::Change {
+            op: op.to_string(),
+            path: display_path(self.path()),
+            value: value.clone(),
+        }
+    }
+}
+
+fn display_path(path: &[PathKey]) -> String {
+    path.iter()
+        .map(|key| match key {
+            PathKey::Key(Yaml::String(key)) => key.clone(),
+            PathKey::Key(key) => format!("{:?}", key),
+            PathKey::Index(index) => index.to_string(),
+            PathKey::NameIndex(name) => name.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// whether two operations may touch the same node. positions in one
+/// sequence shift with any add or delete, so those always overlap.
+fn overlaps(a: &[PathKey], b: &[PathKey]) -> bool {
+    for (a, b) in a.iter().zip(b.iter()) {
+        match (a, b) {
+            (PathKey::Index(_), PathKey::Index(_)) => return true,
+            (a, b) if a != b => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
 pub struct Data {
     diff: Option<Vec<Operation>>,
 }
@@ -27,7 +75,7 @@ impl Data {
         Data { diff: None }
     }
 
-    fn name(yaml: &Yaml) -> Option<&str> {
+    pub(super) fn name(yaml: &Yaml) -> Option<&str> {
         if let Some(name) = yaml["name"].as_str() {
             return Some(name);
         }
@@ -79,8 +127,8 @@ impl Data {
         } else if new.is_sequence() {
             let old_seq = old.as_sequence().unwrap();
             let new_seq = new.as_sequence().unwrap();
-            let has_name = old_seq.len() > 0 && Data::name(&old_seq[0]).is_some();
-            let has_name = has_name || new_seq.len() > 0 && Data::name(&new_seq[0]).is_some();
+            let has_name = !old_seq.is_empty() && Data::name(&old_seq[0]).is_some();
+            let has_name = has_name || !new_seq.is_empty() && Data::name(&new_seq[0]).is_some();
             if has_name {
                 for new_item in new_seq {
                     if let Some(new_name) = Data::name(new_item) {
@@ -132,60 +180,120 @@ impl Data {
         Ok(())
     }
 
-    fn diff(old: &Yaml, new: &Yaml) -> Result<Vec<Operation>> {
+    pub(super) fn diff(old: &Yaml, new: &Yaml) -> Result<Vec<Operation>> {
         let mut diff = vec![];
         let mut path = vec![];
         Data::recurse_diff(old, new, &mut path, &mut diff)?;
         Ok(diff)
     }
 
-    fn seek<'a, 'b>(root: &'a mut Yaml, path: &'b [PathKey]) -> &'a mut Yaml {
+    /// three-way merge, replays the `base` to `local` changes onto `server`.
+    /// fails with the conflicting paths if both sides changed the same node.
+    pub(super) fn merge(base: &Yaml, local: &Yaml, server: &Yaml) -> Result<Yaml> {
+        let ours = Data::diff(base, local)?;
+        let theirs = Data::diff(base, server)?;
+        let conflicts: Vec<String> = ours
+            .iter()
+            .filter(|o| theirs.iter().any(|t| overlaps(o.path(), t.path())))
+            .map(|o| display_path(o.path()))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(anyhow::anyhow!(
+                "both sides changed [{}]",
+                conflicts.join(", ")
+            ));
+        }
+        Data::apply(server, &ours)
+    }
+
+    /// the node at `path`, a missing named item or key is created.
+    fn seek<'a>(root: &'a mut Yaml, path: &[PathKey]) -> Result<&'a mut Yaml> {
         let mut cur = root;
-        for p in path {
+        for (i, p) in path.iter().enumerate() {
+            let not_a = |kind: &str| {
+                anyhow::anyhow!(
+                    "can not apply {}, {} is not a {}",
+                    display_path(path),
+                    display_path(&path[..i]),
+                    kind
+                )
+            };
             cur = match p {
-                PathKey::Key(key) => &mut cur[key],
-                PathKey::Index(index) => &mut cur.as_sequence_mut().unwrap()[*index],
+                PathKey::Key(key) => {
+                    if cur.is_null() {
+                        *cur = Yaml::Mapping(serde_yaml::Mapping::new());
+                    }
+                    let map = cur.as_mapping_mut().ok_or_else(|| not_a("mapping"))?;
+                    if !map.contains_key(key) {
+                        map.insert(key.clone(), Yaml::Null);
+                    }
+                    map.get_mut(key).unwrap()
+                }
+                PathKey::Index(index) => cur
+                    .as_sequence_mut()
+                    .ok_or_else(|| not_a("sequence"))?
+                    .get_mut(*index)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("can not apply {}, no item {}", display_path(path), index)
+                    })?,
                 PathKey::NameIndex(name) => {
-                    let named = cur.as_sequence_mut().expect("Named sequence");
-                    if let Some(idx) = named
-                        .iter()
-                        .position(|item| Data::name(item).map(|n| n == name).unwrap_or(false))
-                    {
-                        &mut named[idx]
-                    } else {
-                        named.push(Yaml::Mapping(serde_yaml::mapping::Mapping::new()));
-                        named.last_mut().unwrap()
+                    let named = cur.as_sequence_mut().ok_or_else(|| not_a("sequence"))?;
+                    match named.iter().position(|item| Data::name(item) == Some(name)) {
+                        Some(idx) => &mut named[idx],
+                        None => {
+                            named.push(Yaml::Mapping(serde_yaml::Mapping::new()));
+                            named.last_mut().unwrap()
+                        }
                     }
                 }
             }
         }
-        cur
+        Ok(cur)
     }
 
-    fn apply(yaml: &Yaml, operations: &[Operation]) -> Result<Yaml> {
+    pub(super) fn apply(yaml: &Yaml, operations: &[Operation]) -> Result<Yaml> {
         let mut yaml = yaml.clone();
 
         for op in operations {
             match op {
                 Operation::Add(path, value) | Operation::Mod(path, value) => {
-                    let cur = Data::seek(&mut yaml, path.as_slice());
-                    *cur = value.clone();
+                    *Data::seek(&mut yaml, path)? = value.clone();
                 }
                 Operation::Del(path, _value) => {
-                    let cur = Data::seek(&mut yaml, &path[..path.len() - 1]);
-                    match path.last().unwrap() {
+                    let Some((last, parent)) = path.split_last() else {
+                        yaml = Yaml::Null;
+                        continue;
+                    };
+                    let cur = Data::seek(&mut yaml, parent)?;
+                    let not_a = |kind: &str| {
+                        anyhow::anyhow!(
+                            "can not delete {}, {} is not a {}",
+                            display_path(path),
+                            display_path(parent),
+                            kind
+                        )
+                    };
+                    match last {
                         PathKey::Key(key) => {
-                            cur.as_mapping_mut().unwrap().remove(key);
+                            cur.as_mapping_mut()
+                                .ok_or_else(|| not_a("mapping"))?
+                                .remove(key);
                         }
                         PathKey::Index(index) => {
-                            cur.as_sequence_mut().unwrap().remove(*index);
+                            let items = cur.as_sequence_mut().ok_or_else(|| not_a("sequence"))?;
+                            if *index >= items.len() {
+                                return Err(anyhow::anyhow!(
+                                    "can not delete {}, no item {}",
+                                    display_path(path),
+                                    index
+                                ));
+                            }
+                            items.remove(*index);
                         }
                         PathKey::NameIndex(name) => {
-                            let named = cur.as_sequence_mut().unwrap();
-                            named.retain(|item| {
-                                item["name"].as_str().unwrap() != name
-                                    && item["key"].as_str().unwrap() != name
-                            });
+                            cur.as_sequence_mut()
+                                .ok_or_else(|| not_a("sequence"))?
+                                .retain(|item| Data::name(item) != Some(name));
                         }
                     };
                 }
@@ -200,27 +308,28 @@ impl super::YamlHandle for Data {
         &mut self,
         _ota: &crate::Ota,
         vehicle: &crate::Vehicle,
-        yaml: &serde_yaml::Value,
-    ) -> anyhow::Result<serde_yaml::Value> {
-        let mut yaml = yaml.clone();
-        if self.diff.is_some()
-            && inquire::Confirm::new(&"[edit] redo last".color("yellow"))
+        doc: &Document,
+    ) -> anyhow::Result<Document> {
+        let redo = match &self.diff {
+            Some(_) => inquire::Confirm::new(&"[edit] redo last".color("yellow"))
                 .with_default(true)
-                .prompt()?
-        {
-            tracing::debug!("redoing last edit, {:?}", self.diff);
-            yaml = Data::apply(&yaml, self.diff.as_ref().unwrap())?;
+                .prompt()?,
+            None => false,
+        };
+        if let (true, Some(diff)) = (redo, &self.diff) {
+            tracing::debug!("redoing last edit, {:?}", diff);
+            doc.update(Data::apply(doc.value(), diff)?)
         } else {
             let edited = inquire::Editor::new(&format!("edit {} yaml", vehicle.name))
-                .with_editor_command(&std::ffi::OsStr::new("vim"))
-                .with_predefined_text(&serde_yaml::to_string(&yaml)?)
+                .with_editor_command(std::ffi::OsStr::new("vim"))
+                .with_predefined_text(doc.text())
                 .with_file_extension(".yaml")
                 .prompt()?;
-            let edited_yaml = serde_yaml::from_str(&edited)?;
-            self.diff = Some(Data::diff(&yaml, &edited_yaml)?);
+            // the edited text is taken as is, so the user's formatting survives
+            let edited = Document::parse(edited)?;
+            self.diff = Some(Data::diff(doc.value(), edited.value())?);
             tracing::debug!("diff: {:?}", self.diff);
-            yaml = edited_yaml;
+            Ok(edited)
         }
-        Ok(yaml)
     }
 }
@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+
+use super::Document;
+
+/// replaces the yaml of a vehicle with a prepared one, e.g. read from `<vehicle>.yaml`.
+pub struct Data {
+    files: HashMap<String, Document>,
+}
+
+impl Data {
+    pub fn new(files: HashMap<String, Document>) -> Self {
+        Data { files }
+    }
+}
+
+impl super::YamlHandle for Data {
+    fn handle(
+        &mut self,
+        _ota: &crate::Ota,
+        vehicle: &crate::Vehicle,
+        _yaml: &Document,
+    ) -> anyhow::Result<Document> {
+        self.files
+            .get(&vehicle.name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no file for {}", vehicle.name))
+    }
+}
@@ -1,10 +1,29 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 mod components;
+mod document;
 mod edit;
+mod file;
+mod set;
+
+pub use document::Document;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Clone,
+    Copy,
+    clap::ValueEnum,
+)]
 pub enum Mode {
     Edit,
     Components,
@@ -24,8 +43,8 @@ pub trait YamlHandle {
         &mut self,
         ota: &super::Ota,
         vehicle: &super::Vehicle,
-        yaml: &serde_yaml::Value,
-    ) -> Result<serde_yaml::Value>;
+        yaml: &Document,
+    ) -> Result<Document>;
 }
 
 pub fn get_handle_mode() -> Result<Option<Mode>> {
@@ -40,3 +59,59 @@ pub fn get_handle(mode: &Mode) -> Box<dyn YamlHandle> {
         Mode::Components => Box::new(components::Data::new()) as Box<dyn YamlHandle>,
     }
 }
+
+/// handles for modes chosen up front, `components` skips the components prompt.
+pub fn get_handles(modes: &[Mode], components: Option<&[String]>) -> Vec<Box<dyn YamlHandle>> {
+    modes
+        .iter()
+        .map(|mode| match (mode, components) {
+            (Mode::Components, Some(components)) => {
+                Box::new(components::Data::with_selected(components)) as Box<dyn YamlHandle>
+            }
+            _ => get_handle(mode),
+        })
+        .collect()
+}
+
+/// handle setting `path=value` assignments.
+pub fn set_handle(assignments: &[String]) -> Result<Box<dyn YamlHandle>> {
+    Ok(Box::new(set::Data::new(assignments)?))
+}
+
+/// handle replacing each vehicle yaml with the prepared one of the same name.
+pub fn file_handle(files: HashMap<String, Document>) -> Box<dyn YamlHandle> {
+    Box::new(file::Data::new(files))
+}
+
+/// one operation of a yaml diff, dot separated path.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Change {
+    /// `add`, `mod` or `del`.
+    pub op: String,
+    pub path: String,
+    /// the new value, or the removed one of a `del`.
+    pub value: serde_yaml::Value,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.op, self.path)
+    }
+}
+
+/// the operations turning `old` into `new`, empty if they are equal.
+pub fn changes(old: &serde_yaml::Value, new: &serde_yaml::Value) -> Result<Vec<Change>> {
+    Ok(edit::Data::diff(old, new)?
+        .iter()
+        .map(|op| op.change())
+        .collect())
+}
+
+/// three-way merge of a local edit and a server change made since `base`.
+pub fn merge(
+    base: &serde_yaml::Value,
+    local: &serde_yaml::Value,
+    server: &serde_yaml::Value,
+) -> Result<serde_yaml::Value> {
+    edit::Data::merge(base, local, server)
+}
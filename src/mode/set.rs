@@ -0,0 +1,86 @@
+use anyhow::Result;
+
+use super::edit::{self, Operation, PathKey};
+type Yaml = serde_yaml::Value;
+
+pub struct Data {
+    assignments: Vec<(String, Yaml)>,
+}
+
+impl Data {
+    pub fn new(assignments: &[String]) -> Result<Self> {
+        let assignments = assignments
+            .iter()
+            .map(|assignment| {
+                let (path, value) = assignment
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("expect path=value, got {}", assignment))?;
+                Ok((path.to_string(), serde_yaml::from_str(value)?))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Data { assignments })
+    }
+
+    /// turn a dot separated path into keys, a sequence is indexed by
+    /// position when the segment is a number and by item name otherwise.
+    /// missing or null nodes on the way become mappings, scalars are an error.
+    fn resolve(yaml: &Yaml, path: &str) -> Result<Vec<PathKey>> {
+        let mut keys = vec![];
+        let mut cur = Some(yaml);
+        for (i, segment) in path.split('.').enumerate() {
+            match cur {
+                Some(Yaml::Sequence(seq)) => {
+                    if let Ok(index) = segment.parse::<usize>() {
+                        cur = seq.get(index);
+                        if cur.is_none() {
+                            return Err(anyhow::anyhow!("{} out of range in {}", index, path));
+                        }
+                        keys.push(PathKey::Index(index));
+                    } else {
+                        cur = seq
+                            .iter()
+                            .find(|item| edit::Data::name(item) == Some(segment));
+                        if cur.is_none() {
+                            return Err(anyhow::anyhow!("no item named {} in {}", segment, path));
+                        }
+                        keys.push(PathKey::NameIndex(segment.to_string()));
+                    }
+                }
+                Some(Yaml::Mapping(_) | Yaml::Null) | None => {
+                    let key = Yaml::String(segment.to_string());
+                    cur = cur.and_then(|c| c.get(&key));
+                    keys.push(PathKey::Key(key));
+                }
+                Some(_) => {
+                    let parent = path.split('.').take(i).collect::<Vec<_>>().join(".");
+                    return Err(anyhow::anyhow!(
+                        "can not set {}, {} is not a mapping",
+                        path,
+                        parent
+                    ));
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+impl super::YamlHandle for Data {
+    fn handle(
+        &mut self,
+        _ota: &crate::Ota,
+        vehicle: &crate::Vehicle,
+        doc: &super::Document,
+    ) -> anyhow::Result<super::Document> {
+        let yaml = doc.value();
+        let operations = self
+            .assignments
+            .iter()
+            .map(|(path, value)| {
+                tracing::debug!("set {} of {} to {:?}", path, vehicle.name, value);
+                Ok(Operation::Mod(Data::resolve(yaml, path)?, value.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        doc.update(edit::Data::apply(yaml, &operations)?)
+    }
+}
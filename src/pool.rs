@@ -0,0 +1,89 @@
+//! the logged in clients of a run. every request leases an idle one, so with
+//! `--jobs` requests of the same kind are in flight on different connections.
+
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::client::OtaClient;
+
+pub struct Pool {
+    /// the client logged in first, the others are opened from its session.
+    main: Arc<OtaClient>,
+    idle: Mutex<Vec<Arc<OtaClient>>>,
+    /// one permit per idle client.
+    ready: Semaphore,
+    /// clients opened so far, the main one included.
+    size: tokio::sync::Mutex<usize>,
+}
+
+/// a client used by one request, back to the pool on drop.
+pub struct Lease<'a> {
+    pool: &'a Pool,
+    client: Option<Arc<OtaClient>>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Pool {
+    pub fn new(client: OtaClient) -> Pool {
+        let main = Arc::new(client);
+        Pool {
+            idle: Mutex::new(vec![main.clone()]),
+            main,
+            ready: Semaphore::new(1),
+            size: tokio::sync::Mutex::new(1),
+        }
+    }
+
+    /// the first client, for what is the same on all of them, e.g. the user id.
+    pub fn main(&self) -> &OtaClient {
+        &self.main
+    }
+
+    /// open clients until there are `size`, one that fails to connect
+    /// only leaves fewer jobs running at once.
+    pub async fn grow(&self, size: usize) {
+        let mut opened = self.size.lock().await;
+        while *opened < size {
+            match self.main.connect_again().await {
+                Ok(client) => {
+                    self.idle.lock().unwrap().push(Arc::new(client));
+                    self.ready.add_permits(1);
+                    *opened += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("open connection {} failed: {}", *opened + 1, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// wait for an idle client.
+    pub async fn lease(&self) -> Lease<'_> {
+        let permit = self.ready.acquire().await.expect("pool is never closed");
+        let client = self.idle.lock().unwrap().pop();
+        Lease {
+            pool: self,
+            client,
+            _permit: permit,
+        }
+    }
+}
+
+impl Deref for Lease<'_> {
+    type Target = OtaClient;
+
+    fn deref(&self) -> &OtaClient {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.idle.lock().unwrap().push(client);
+        }
+    }
+}
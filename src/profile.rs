@@ -0,0 +1,130 @@
+//! named hosts and accounts, read from `~/.config/ota-yaml/config.yaml`:
+//!
+//! ```yaml
+//! default: prod
+//! profiles:
+//!   prod:
+//!     host: ota.example.com
+//!     username: ci
+//!     organization_id: 1
+//!     push_type: none
+//!   staging:
+//!     endpoint: wss://staging.example.com/user_client
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer};
+
+use crate::carside::PushType;
+use crate::cli::Cli;
+use crate::local;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// the profile used without `--profile`.
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// defaults for flags that are not given, flags and the environment win.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub endpoint: Option<String>,
+    pub username: Option<String>,
+    pub organization_id: Option<i32>,
+    /// `none`, `silent` or `force`, like `--push-type`.
+    #[serde(default, deserialize_with = "push_type")]
+    pub push_type: Option<PushType>,
+}
+
+fn push_type<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PushType>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    clap::ValueEnum::from_str(&name, true)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn path() -> Result<PathBuf> {
+    Ok(local::config_dir()?.join("config.yaml"))
+}
+
+/// the config file, a missing one has no profiles.
+pub fn load() -> Result<Config> {
+    let path = path()?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(anyhow::anyhow!("read {:?} failed: {}", path, e)),
+    };
+    serde_yaml::from_str(&text).map_err(|e| anyhow::anyhow!("invalid {:?}: {}", path, e))
+}
+
+impl Config {
+    /// the named profile, or else the default one. an unknown name is an error.
+    pub fn select(&self, name: Option<&str>) -> Result<Option<(String, Profile)>> {
+        let Some(name) = name.or(self.default.as_deref()) else {
+            return Ok(None);
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(Some((name.to_string(), profile.clone()))),
+            None => Err(anyhow::anyhow!(
+                "no profile {} in {:?}",
+                name,
+                path().unwrap_or_default()
+            )),
+        }
+    }
+
+    pub fn list(&self) {
+        for (name, profile) in &self.profiles {
+            let default = if self.default.as_deref() == Some(name) {
+                " (default)"
+            } else {
+                ""
+            };
+            let target = profile
+                .endpoint
+                .as_deref()
+                .or(profile.host.as_deref())
+                .unwrap_or("-");
+            println!(
+                "{}{}\t{}\t{}\torg {}\tpush {}",
+                name,
+                default,
+                target,
+                profile.username.as_deref().unwrap_or("-"),
+                profile
+                    .organization_id
+                    .map_or("-".to_string(), |id| id.to_string()),
+                profile.push_type.map_or("-".to_string(), |p| {
+                    let value = clap::ValueEnum::to_possible_value(&p).unwrap();
+                    value.get_name().to_string()
+                }),
+            );
+        }
+    }
+}
+
+impl Profile {
+    /// fill in what the command line left out.
+    pub fn apply(&self, cli: &mut Cli) {
+        // a --host or --endpoint replaces the whole target of the profile
+        if cli.conn.endpoint.is_none() && cli.conn.host.is_none() {
+            cli.conn.endpoint = self.endpoint.clone();
+            cli.conn.host = self.host.clone();
+        }
+        if cli.login.username.is_none() {
+            cli.login.username = self.username.clone();
+        }
+        if cli.login.organization_id.is_none() {
+            cli.login.organization_id = self.organization_id;
+        }
+    }
+}
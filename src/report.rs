@@ -0,0 +1,155 @@
+//! per vehicle result of a run, as json or junit xml for ci.
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::api::Vehicle;
+use crate::carside::PushType;
+use crate::cli::Output;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Status {
+    Modified,
+    /// the edit changed nothing.
+    Unchanged,
+    /// left alone by choice, e.g. declined in the preview.
+    Skipped,
+    Failed,
+    /// not started, the run stopped at an earlier failure.
+    NotReached,
+    /// saved, then restored by `--rollback` after another vehicle failed.
+    RolledBack,
+}
+
+/// what `Carside` did with a saved config.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Published {
+    pub config_id: i32,
+    /// `None` if not tried, an earlier approval was refused.
+    pub approved: Option<bool>,
+    pub push_type: PushType,
+    /// `None` if not tried.
+    pub pushed: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub vehicle_id: i32,
+    pub vehicle_name: String,
+    pub status: Status,
+    pub error: Option<String>,
+    /// changed yaml paths, `None` if nothing was edited.
+    pub changes: Option<usize>,
+    pub published: Option<Published>,
+}
+
+impl Entry {
+    /// what the server refused after the save, e.g. the approval.
+    fn refused(&self) -> Option<&'static str> {
+        let published = self.published.as_ref()?;
+        if published.approved == Some(false) {
+            Some("approve refused")
+        } else if published.pushed == Some(false) {
+            Some("push refused")
+        } else {
+            None
+        }
+    }
+
+    pub fn new(vehicle: &Vehicle, status: Status) -> Entry {
+        Entry {
+            vehicle_id: vehicle.id,
+            vehicle_name: vehicle.name.clone(),
+            status,
+            error: None,
+            changes: None,
+            published: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report<'a> {
+    /// journal id, see `ota-yaml rollback --run`.
+    pub run: &'a str,
+    pub vehicles: &'a [Entry],
+}
+
+impl Report<'_> {
+    /// write the report to `path` or stdout, nothing for `Output::Text`.
+    pub fn write(&self, output: Output, path: Option<&Path>) -> Result<()> {
+        let text = match output {
+            Output::Text => return Ok(()),
+            Output::Json => serde_json::to_string_pretty(self)? + "\n",
+            Output::Junit => self.junit(),
+        };
+        match path {
+            Some(path) => std::fs::write(path, text)
+                .map_err(|e| anyhow::anyhow!("write report {:?} failed: {}", path, e)),
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        }
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.vehicles.iter().filter(|e| e.status == status).count()
+    }
+
+    /// one testcase per vehicle, skipped and failed ones marked as such.
+    /// a refused approve or push fails the testcase too.
+    fn junit(&self) -> String {
+        let refused = self
+            .vehicles
+            .iter()
+            .filter(|e| e.status != Status::Failed && e.refused().is_some())
+            .count();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml += &format!(
+            "<testsuite name=\"ota-yaml\" id=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+            escape(self.run),
+            self.vehicles.len(),
+            self.count(Status::Failed) + refused,
+            self.count(Status::Skipped)
+                + self.count(Status::NotReached)
+                + self.count(Status::RolledBack),
+        );
+        for entry in self.vehicles {
+            xml += &format!(
+                "  <testcase classname=\"ota-yaml\" name=\"{}\">\n",
+                escape(&entry.vehicle_name)
+            );
+            match (entry.status, entry.refused()) {
+                (Status::Failed, _) => {
+                    let message = entry.error.as_deref().unwrap_or("failed");
+                    xml += &format!("    <failure message=\"{}\"/>\n", escape(message));
+                }
+                (_, Some(message)) => xml += &format!("    <failure message=\"{}\"/>\n", message),
+                (Status::Skipped, _) => xml += "    <skipped/>\n",
+                (Status::NotReached, _) => xml += "    <skipped message=\"not reached\"/>\n",
+                (Status::RolledBack, _) => xml += "    <skipped message=\"rolled back\"/>\n",
+                (Status::Modified | Status::Unchanged, None) => {}
+            }
+            xml += &format!(
+                "    <system-out>{}</system-out>\n",
+                escape(&serde_json::to_string(entry).unwrap_or_default())
+            );
+            xml += "  </testcase>\n";
+        }
+        xml + "</testsuite>\n"
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,149 @@
+//! how request and response envelopes travel between `Connenction` and the server.
+//!
+//! a `Transport` opens `Link`s, a pair of channels. `Connenction` opens a new
+//! one whenever the previous link closes, so reconnects, timeouts and dispatch
+//! stay the same whatever carries the frames.
+
+use std::path::PathBuf;
+
+use futures::future::BoxFuture;
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_tungstenite::{connect_async_tls_with_config, tungstenite::protocol::Message, Connector};
+
+use crate::error::{OtaError, Result};
+
+pub use crate::connection::{Request, Response};
+
+/// an open link to the server. it is closed once `rx` ends,
+/// dropping `tx` asks the transport to close it.
+pub struct Link {
+    /// requests to send.
+    pub tx: UnboundedSender<Request>,
+    /// responses received, in order.
+    pub rx: UnboundedReceiver<Response>,
+}
+
+impl Link {
+    /// a link and the other ends of its channels, for transports to pump.
+    pub fn pair() -> (Link, UnboundedReceiver<Request>, UnboundedSender<Response>) {
+        let (tx, requests) = mpsc::unbounded();
+        let (responses, rx) = mpsc::unbounded();
+        (Link { tx, rx }, requests, responses)
+    }
+}
+
+pub trait Transport: Send + Sync {
+    /// where the frames go, for logs.
+    fn endpoint(&self) -> &str;
+
+    /// open a new link, called again after the previous one closed.
+    fn open(&self) -> BoxFuture<'_, Result<Link>>;
+}
+
+/// how to verify the server certificate of a `wss://` endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// extra PEM bundle trusted besides the system roots.
+    pub ca_file: Option<PathBuf>,
+    /// accept any certificate and host name, only meant for lab servers.
+    pub insecure: bool,
+}
+
+impl TlsOptions {
+    fn connector(&self) -> Result<Connector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(ca_file) = &self.ca_file {
+            let bundle = std::fs::read_to_string(ca_file).map_err(|e| {
+                OtaError::Transport(format!("read ca file {:?} failed: {}", ca_file, e))
+            })?;
+            let end = "-----END CERTIFICATE-----";
+            for pem in bundle.split_inclusive(end).filter(|pem| pem.contains(end)) {
+                builder.add_root_certificate(native_tls::Certificate::from_pem(pem.as_bytes())?);
+            }
+        }
+        if self.insecure {
+            tracing::warn!("tls certificate verification disabled");
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        Ok(Connector::NativeTls(builder.build()?))
+    }
+}
+
+/// envelopes as json text frames over a websocket.
+pub struct WebSocket {
+    url: String,
+    connector: Connector,
+}
+
+impl WebSocket {
+    /// a transport to a `ws://` or `wss://` endpoint url.
+    pub fn new(url: &str, tls: &TlsOptions) -> Result<WebSocket> {
+        let connector = match url.split_once("://") {
+            Some(("wss", _)) => tls.connector()?,
+            Some(("ws", _)) => Connector::Plain,
+            _ => {
+                return Err(OtaError::Transport(format!(
+                    "unsupported endpoint {}, use ws:// or wss://",
+                    url
+                )))
+            }
+        };
+        Ok(WebSocket {
+            url: url.to_string(),
+            connector,
+        })
+    }
+}
+
+impl Transport for WebSocket {
+    fn endpoint(&self) -> &str {
+        &self.url
+    }
+
+    fn open(&self) -> BoxFuture<'_, Result<Link>> {
+        Box::pin(async move {
+            let (stream, _) =
+                connect_async_tls_with_config(&self.url, None, Some(self.connector.clone()))
+                    .await?;
+            let (link, requests, responses) = Link::pair();
+            let (mut write, read) = stream.split();
+            let t = async move {
+                let mut requests = requests;
+                while let Some(req) = requests.next().await {
+                    let text = match serde_json::to_string(&req) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            tracing::error!("drop unencodable request {:?}: {}", req, e);
+                            continue;
+                        }
+                    };
+                    if write.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+            };
+            let r = read.try_for_each(move |msg| {
+                tracing::trace!("recv: {:?}", msg);
+                match msg {
+                    Message::Text(text) => match serde_json::from_str::<Response>(&text) {
+                        Ok(resp) => {
+                            let _ = responses.unbounded_send(resp);
+                        }
+                        Err(e) => tracing::warn!("drop undecodable message {}: {}", text, e),
+                    },
+                    Message::Close(frame) => tracing::debug!("recv close: {:?}", frame),
+                    other => tracing::trace!("ignore non text message: {:?}", other),
+                }
+                futures_util::future::ok(())
+            });
+            tokio::spawn(async move {
+                futures_util::pin_mut!(t, r);
+                futures_util::future::select(t, r).await;
+            });
+            Ok(link)
+        })
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::time::Duration;
+
+use common::{home, server, LOCK};
+use ota_yaml::mock::{Fault, MockServer, TokenExpiry};
+use ota_yaml::EventType;
+
+mod common;
+
+fn count(server: &MockServer, event_type: EventType) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|e| **e == event_type)
+        .count()
+}
+
+/// the only cached token file.
+fn token_file(home: &Path) -> std::path::PathBuf {
+    let dir = home.join(".cache/ota-yaml/tokens");
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    files.pop().unwrap()
+}
+
+fn cached_token(home: &Path) -> serde_json::Value {
+    let text = std::fs::read_to_string(token_file(home)).unwrap();
+    serde_json::from_str(&text).unwrap()
+}
+
+/// seconds from now until the cached token expires.
+fn cached_ttl(home: &Path) -> i64 {
+    let token = cached_token(home);
+    let at: chrono::DateTime<chrono::Local> =
+        serde_json::from_value(token["expires_at"].clone()).unwrap();
+    (at - chrono::Local::now()).num_seconds()
+}
+
+#[tokio::test]
+async fn token_expiry_comes_from_the_server() {
+    let _lock = LOCK.lock().await;
+
+    for expiry in [
+        TokenExpiry::ExpiresIn(Duration::from_secs(7200)),
+        TokenExpiry::Jwt(Duration::from_secs(7200)),
+    ] {
+        let home = home();
+        let (server, _) = server().await;
+        server.set_token_expiry(expiry);
+        common::run(&server.url(), &["login"]).await.unwrap();
+        let ttl = cached_ttl(home.path());
+        assert!((7100..=7200).contains(&ttl), "{:?}: {}", expiry, ttl);
+    }
+
+    // without one the token is used until the server rejects it
+    let home = home();
+    let (server, _) = server().await;
+    common::run(&server.url(), &["login"]).await.unwrap();
+    assert!(cached_token(home.path())["expires_at"].is_null());
+    common::run(&server.url(), &["login"]).await.unwrap();
+    assert_eq!(count(&server, EventType::TokenLoginRequest), 1);
+}
+
+#[tokio::test]
+async fn expired_token_is_not_tried() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, _) = server().await;
+    server.set_token_expiry(TokenExpiry::ExpiresIn(Duration::ZERO));
+
+    common::run(&server.url(), &["login"]).await.unwrap();
+    assert!(cached_ttl(home.path()) <= 0);
+    common::run(&server.url(), &["login"]).await.unwrap();
+    assert_eq!(count(&server, EventType::TokenLoginRequest), 0);
+    assert_eq!(count(&server, EventType::LoginRequest), 2);
+}
+
+#[tokio::test]
+async fn broken_token_cache_falls_back_to_password() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, _) = server().await;
+    common::run(&server.url(), &["login"]).await.unwrap();
+    std::fs::write(token_file(home.path()), "not json").unwrap();
+
+    common::run(&server.url(), &["login"]).await.unwrap();
+    assert_eq!(count(&server, EventType::LoginRequest), 2);
+}
+
+#[tokio::test]
+async fn session_expired_mid_run_logs_in_again() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    server.inject(
+        EventType::OtaFetchVehicleTemplateItemContents,
+        Fault::Code(50001, "Token invalid, please sign in".to_string()),
+    );
+
+    common::run(&server.url(), &["get", "-v", "car-1"])
+        .await
+        .unwrap();
+    assert_eq!(count(&server, EventType::LoginRequest), 2);
+    assert_eq!(
+        count(&server, EventType::OtaFetchVehicleTemplateItemContents),
+        2
+    );
+}
+
+#[tokio::test]
+async fn configured_expired_code_logs_in_again() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    let fetch = EventType::OtaFetchVehicleTemplateItemContents;
+    server.inject(fetch, Fault::Code(4010, "denied".to_string()));
+
+    let e = common::run(&server.url(), &["get", "-v", "car-1"])
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("denied"), "{}", e);
+
+    server.inject(fetch, Fault::Code(4010, "denied".to_string()));
+    common::run(
+        &server.url(),
+        &["--expired-code", "4010", "get", "-v", "car-1"],
+    )
+    .await
+    .unwrap();
+    // the second run starts from the cached token
+    assert_eq!(count(&server, EventType::LoginRequest), 2);
+}
+
+#[tokio::test]
+async fn cached_token_is_bound_to_its_user() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, _) = server().await;
+    server.add_user("alice", "a");
+    let bob = server.add_user("bob", "b");
+    let url = server.url();
+
+    let alice = ["--username", "alice", "--password", "a", "login"];
+    common::run_raw(&[&["--endpoint", &url][..], &alice].concat())
+        .await
+        .unwrap();
+    let bob_set = [
+        "--username",
+        "bob",
+        "--password",
+        "b",
+        "set",
+        "-v",
+        "car-1",
+        "a.b=2",
+        "--manual",
+        "false",
+        "--auto-publish",
+        "false",
+    ];
+    common::run_raw(&[&["--endpoint", &url][..], &bob_set].concat())
+        .await
+        .unwrap();
+    // alice's token is not reused for bob
+    assert_eq!(count(&server, EventType::TokenLoginRequest), 0);
+    assert_eq!(server.saves()[0].user_id, bob);
+    assert_eq!(cached_token(home.path())["username"], "bob");
+}
+
+#[tokio::test]
+async fn cached_token_is_bound_to_its_organization() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, _) = server().await;
+    server.add_organization("admin", 7, "fleet");
+
+    // not a member of the default organization 1, and no terminal to ask
+    assert!(common::run(&server.url(), &["login"]).await.is_err());
+    common::run(&server.url(), &["--organization-id", "7", "login"])
+        .await
+        .unwrap();
+    assert_eq!(cached_token(home.path())["organization_id"], 7);
+
+    // reused without a choice, but not for another organization
+    common::run(&server.url(), &["login"]).await.unwrap();
+    assert_eq!(count(&server, EventType::TokenLoginRequest), 1);
+    assert!(
+        common::run(&server.url(), &["--organization-id", "3", "login"])
+            .await
+            .is_err()
+    );
+    assert_eq!(count(&server, EventType::TokenLoginRequest), 1);
+    assert_eq!(count(&server, EventType::LoginRequest), 3);
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use ota_yaml::mock::{Fault, MockServer};
+use ota_yaml::transport::TlsOptions;
+use ota_yaml::{EventType, OtaClient, OtaError, PushType};
+
+/// a server with cars a and b, and a client logged into it.
+async fn logged_in(timeout: Duration) -> (MockServer, OtaClient, i32, i32) {
+    let server = MockServer::start().await.unwrap();
+    server.add_user("admin", "secret");
+    let a = server.add_vehicle("a", "name: a\n");
+    let b = server.add_vehicle("b", "name: b\n");
+    let mut client = OtaClient::connect(&server.url(), &TlsOptions::default(), timeout)
+        .await
+        .unwrap();
+    client.login("admin", "secret").await.unwrap();
+    (server, client, a, b)
+}
+
+#[tokio::test]
+async fn client_round_trip() {
+    let server = MockServer::start().await.unwrap();
+    let user_id = server.add_user("admin", "secret");
+    let vehicle_id = server.add_vehicle("car-1", "a: 1\n");
+
+    let mut client = OtaClient::connect(
+        &server.url(),
+        &TlsOptions::default(),
+        Duration::from_secs(5),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        client.login("admin", "wrong").await,
+        Err(OtaError::Server { .. })
+    ));
+    let login = client.login("admin", "secret").await.unwrap();
+    assert_eq!(login.id, user_id);
+    assert_eq!(client.user_id(), user_id);
+
+    let vehicles = client.list_vehicles().await.unwrap();
+    assert_eq!(vehicles.len(), 1);
+    let vehicle = &vehicles[0];
+    assert_eq!((vehicle.id, vehicle.name.as_str()), (vehicle_id, "car-1"));
+
+    let old = client.fetch_template(vehicle.id).await.unwrap();
+    assert_eq!(old, "a: 1\n");
+    let saved = client
+        .save_template(vehicle.id, &old, "a: 2\n")
+        .await
+        .unwrap();
+    assert!(saved.ok);
+    assert_eq!(server.template(vehicle_id).unwrap(), "a: 2\n");
+
+    let config_id = client.publish_config(vehicle).await.unwrap();
+    assert!(client.approve_config(config_id).await.unwrap());
+    assert!(client
+        .push_config(vehicle.id, config_id, PushType::Slient)
+        .await
+        .unwrap());
+    let publishes = server.publishes();
+    assert_eq!(publishes[0].pushed.as_deref(), Some("UPGRADE_SELIENT"));
+}
+
+#[tokio::test]
+async fn cancelled_request_does_not_answer_the_next_one() {
+    let (server, client, a, b) = logged_in(Duration::from_secs(5)).await;
+    let fetch = EventType::OtaFetchVehicleTemplateItemContents;
+    // the reply to a would arrive while b waits
+    server.inject(fetch, Fault::Delay(Duration::from_millis(500)));
+    server.inject(fetch, Fault::Delay(Duration::from_secs(1)));
+
+    let cancelled =
+        tokio::time::timeout(Duration::from_millis(100), client.fetch_template(a)).await;
+    assert!(cancelled.is_err());
+    assert_eq!(client.fetch_template(b).await.unwrap(), "name: b\n");
+}
+
+#[tokio::test]
+async fn timeout_of_one_event_type() {
+    let (server, mut client, a, _) = logged_in(Duration::from_millis(500)).await;
+    client.set_timeout(EventType::OtaAddVehicleTemplateItem, Duration::from_secs(5));
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Delay(Duration::from_secs(1)),
+    );
+    assert!(
+        client
+            .save_template(a, "name: a\n", "name: c\n")
+            .await
+            .unwrap()
+            .ok
+    );
+
+    server.inject(
+        EventType::OtaFetchVehicleTemplateItemContents,
+        Fault::Delay(Duration::from_secs(1)),
+    );
+    assert!(matches!(
+        client.fetch_template(a).await,
+        Err(OtaError::Timeout { .. })
+    ));
+}
@@ -0,0 +1,48 @@
+#![allow(dead_code)]
+
+use clap::Parser;
+use ota_yaml::cli::Cli;
+use ota_yaml::mock::MockServer;
+use ota_yaml::Ota;
+
+pub const YAML: &str = "# car config\na:\n  b: 1 # keep me\n  c: [x, y]\n";
+
+/// runs share the token cache under `$HOME`, so they go one at a time.
+pub static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// a server with user admin, car-1 holding `YAML` and car-2, and the id of car-1.
+pub async fn server() -> (MockServer, i32) {
+    let server = MockServer::start().await.unwrap();
+    server.add_user("admin", "secret");
+    let id = server.add_vehicle("car-1", YAML);
+    server.add_vehicle("car-2", "a:\n  b: 7\n");
+    (server, id)
+}
+
+/// point `$HOME` at an empty directory, so no token is cached.
+pub fn home() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_var("HOME", dir.path());
+    dir
+}
+
+pub async fn run(url: &str, args: &[&str]) -> anyhow::Result<()> {
+    let mut argv = vec![
+        "ota-yaml",
+        "--endpoint",
+        url,
+        "--username",
+        "admin",
+        "--password",
+        "secret",
+    ];
+    argv.extend_from_slice(args);
+    Ota::run(Cli::parse_from(argv)).await
+}
+
+/// run with exactly `args`, no endpoint or login filled in.
+pub async fn run_raw(args: &[&str]) -> anyhow::Result<()> {
+    let mut argv = vec!["ota-yaml"];
+    argv.extend_from_slice(args);
+    Ota::run(Cli::parse_from(argv)).await
+}
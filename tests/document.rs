@@ -0,0 +1,111 @@
+use ota_yaml::Document;
+use serde_yaml::Value;
+
+/// the text after changing the value of `text` with `f`.
+fn update(text: &str, f: impl FnOnce(&mut Value)) -> String {
+    let doc = Document::parse(text.to_string()).unwrap();
+    let mut value = doc.value().clone();
+    f(&mut value);
+    let new = doc.update(value.clone()).unwrap();
+    assert_eq!(new.value(), &value);
+    new.text().to_string()
+}
+
+fn seq<'a>(value: &'a mut Value, key: &str) -> &'a mut Vec<Value> {
+    value[key].as_sequence_mut().unwrap()
+}
+
+#[test]
+fn scalar_keeps_comments_and_order() {
+    let text = "# head\nb: 1 # keep\na: x\n";
+    let new = update(text, |v| v["b"] = 2.into());
+    assert_eq!(new, "# head\nb: 2 # keep\na: x\n");
+}
+
+#[test]
+fn key_is_added_after_its_siblings() {
+    let text = "a:\n  b: 1 # one\n  c: [x, y]\nd: 1 # after\n";
+    let new = update(text, |v| v["a"]["e"] = 5.into());
+    assert_eq!(new, "a:\n  b: 1 # one\n  c: [x, y]\n  e: 5\nd: 1 # after\n");
+}
+
+#[test]
+fn key_is_deleted_with_its_line() {
+    let text = "a: 1 # one\nb:\n  c: 2 # two\nd: 3 # three\n";
+    let new = update(text, |v| {
+        v.as_mapping_mut().unwrap().remove("b");
+    });
+    assert_eq!(new, "a: 1 # one\nd: 3 # three\n");
+}
+
+#[test]
+fn named_item_is_found_by_name() {
+    let text = "items:\n  - name: x # first\n    v: 1\n  - name: y\n    v: 2 # second\n";
+    let new = update(text, |v| v["items"][1]["v"] = 3.into());
+    assert_eq!(
+        new,
+        "items:\n  - name: x # first\n    v: 1\n  - name: y\n    v: 3 # second\n"
+    );
+}
+
+#[test]
+fn indexed_items_are_added_and_deleted() {
+    let text = "list:\n  - 1 # one\n  - 2 # two\nend: true\n";
+    let new = update(text, |v| seq(v, "list").push(3.into()));
+    assert_eq!(new, "list:\n  - 1 # one\n  - 2 # two\n  - 3\nend: true\n");
+    // unnamed items are compared by position
+    let new = update(text, |v| {
+        seq(v, "list").pop();
+    });
+    assert_eq!(new, "list:\n  - 1 # one\nend: true\n");
+}
+
+#[test]
+fn named_item_is_deleted_by_name() {
+    let text = "items:\n  - name: x # first\n    v: 1\n  - name: y # second\n    v: 2\n";
+    let new = update(text, |v| {
+        seq(v, "items").remove(0);
+    });
+    assert_eq!(new, "items:\n  - name: y # second\n    v: 2\n");
+}
+
+#[test]
+fn flow_collection_scalars_are_patched() {
+    let text = "a: [x, y] # flow\nm: {k: 1, l: 2}\n";
+    let new = update(text, |v| {
+        v["a"][1] = "z".into();
+        v["m"]["l"] = 3.into();
+    });
+    assert_eq!(new, "a: [x, z] # flow\nm: {k: 1, l: 3}\n");
+}
+
+#[test]
+fn quoted_scalars_keep_their_quotes() {
+    let text = "s: 'it''s' # single\nd: \"x\" # double\np: plain\n";
+    let new = update(text, |v| {
+        v["s"] = "it's ok".into();
+        v["d"] = "a\"b".into();
+    });
+    assert_eq!(
+        new,
+        "s: 'it''s ok' # single\nd: \"a\\\"b\" # double\np: plain\n"
+    );
+}
+
+#[test]
+fn unpatchable_change_rewrites_the_text() {
+    // an item can't be added to a flow sequence in place
+    let text = "a: [x, y] # flow\nb: 1\n";
+    let new = update(text, |v| seq(v, "a").push("z".into()));
+    assert_eq!(new, "a:\n- x\n- y\n- z\nb: 1\n");
+}
+
+#[test]
+fn text_after_non_ascii_keeps_its_offsets() {
+    let text = "# 中文注释\na:\n  b: 1 # 一\n  c: '值' # 二\n";
+    let new = update(text, |v| {
+        v["a"]["b"] = 2.into();
+        v["a"]["c"] = "新值".into();
+    });
+    assert_eq!(new, "# 中文注释\na:\n  b: 2 # 一\n  c: '新值' # 二\n");
+}
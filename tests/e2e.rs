@@ -0,0 +1,918 @@
+use std::time::Duration;
+
+use common::{home, server, LOCK, YAML};
+use ota_yaml::mock::{Fault, MockServer};
+use ota_yaml::{EventType, OtaError};
+
+mod common;
+
+#[tokio::test]
+async fn set_save_publish_and_push() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, id) = server().await;
+
+    common::run(
+        &server.url(),
+        &[
+            "set",
+            "-v",
+            "car-1",
+            "a.b=2",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "true",
+            "--push-type",
+            "force",
+        ],
+    )
+    .await
+    .unwrap();
+
+    let saves = server.saves();
+    assert_eq!(saves.len(), 1);
+    assert_eq!(saves[0].old_config, YAML);
+    assert_eq!(
+        server.template(id).unwrap(),
+        "# car config\na:\n  b: 2 # keep me\n  c: [x, y]\n"
+    );
+    let publishes = server.publishes();
+    assert_eq!(publishes.len(), 1);
+    assert_eq!(publishes[0].vehicle_id, id);
+    assert!(publishes[0].approved);
+    assert_eq!(publishes[0].pushed.as_deref(), Some("UPGRADE_ENFORCE"));
+}
+
+#[tokio::test]
+async fn server_error_fails_the_run() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Code(500, "disk full".to_string()),
+    );
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "set",
+            "--all",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::Server { code: 500, .. })
+    ));
+    assert!(server.saves().is_empty());
+}
+
+#[tokio::test]
+async fn slow_reply_times_out() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    server.inject(
+        EventType::OtaFetchVehicleTemplateTable,
+        Fault::Delay(Duration::from_secs(3)),
+    );
+
+    let e = common::run(&server.url(), &["--timeout", "1", "vehicles", "list"])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::Timeout {
+            event_type: EventType::OtaFetchVehicleTemplateTable,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn request_after_a_timeout_gets_its_own_reply() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, id) = server().await;
+    server.inject(
+        EventType::OtaFetchVehicleTemplateItemContents,
+        Fault::Delay(Duration::from_secs(30)),
+    );
+
+    common::run(
+        &server.url(),
+        &[
+            "--timeout",
+            "1",
+            "--retries",
+            "2",
+            "set",
+            "-v",
+            "car-1",
+            "a.b=2",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap();
+    // the late reply would have answered the retry, so the link was replaced
+    let fetches = server
+        .requests()
+        .iter()
+        .filter(|e| **e == EventType::OtaFetchVehicleTemplateItemContents)
+        .count();
+    assert_eq!(fetches, 2);
+    assert!(server.template(id).unwrap().contains("b: 2"));
+}
+
+#[tokio::test]
+async fn dropped_fetch_is_retried_after_resume() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    server.inject(EventType::OtaFetchVehicleTemplateItemContents, Fault::Drop);
+
+    common::run(&server.url(), &["get", "-v", "car-1"])
+        .await
+        .unwrap();
+
+    let requests = server.requests();
+    let dropped = requests
+        .iter()
+        .position(|e| *e == EventType::OtaFetchVehicleTemplateItemContents)
+        .unwrap();
+    // the retry runs on a new socket, so the session is resumed first
+    assert_eq!(
+        requests[dropped + 1..],
+        [
+            EventType::TokenLoginRequest,
+            EventType::OtaFetchVehicleTemplateItemContents
+        ]
+    );
+}
+
+#[tokio::test]
+async fn expired_token_logs_in_again() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+
+    common::run(&server.url(), &["login"]).await.unwrap();
+    server.expire_tokens();
+    common::run(&server.url(), &["vehicles", "list"])
+        .await
+        .unwrap();
+
+    let logins = server
+        .requests()
+        .iter()
+        .filter(|e| **e == EventType::LoginRequest)
+        .count();
+    assert_eq!(logins, 2);
+}
+
+#[tokio::test]
+async fn jobs_fetch_and_save_vehicles_concurrently() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    server.add_vehicle("car-3", "a:\n  b: 9\n");
+    for _ in 0..3 {
+        for event_type in [
+            EventType::OtaFetchVehicleTemplateItemContents,
+            EventType::OtaAddVehicleTemplateItem,
+        ] {
+            server.inject(event_type, Fault::Delay(Duration::from_millis(800)));
+        }
+    }
+
+    let started = std::time::Instant::now();
+    common::run(
+        &server.url(),
+        &[
+            "--jobs",
+            "3",
+            "set",
+            "--all",
+            "a.b=5",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap();
+    // one after another the fetches and saves alone would take 4.8s
+    assert!(started.elapsed() < Duration::from_millis(3000));
+    assert_eq!(server.saves().len(), 3);
+}
+
+#[tokio::test]
+async fn jobs_keep_each_reply_with_its_vehicle() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let server = MockServer::start().await.unwrap();
+    server.add_user("admin", "secret");
+    let a = server.add_vehicle("car-a", "who: a\n");
+    let b = server.add_vehicle("car-b", "who: b\n");
+    // car-a's fetch is answered after car-b's would be
+    server.inject(
+        EventType::OtaFetchVehicleTemplateItemContents,
+        Fault::Delay(Duration::from_millis(500)),
+    );
+
+    common::run(
+        &server.url(),
+        &[
+            "--jobs",
+            "2",
+            "set",
+            "--all",
+            "x=1",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(server.template(a).unwrap(), "who: a\nx: 1\n");
+    assert_eq!(server.template(b).unwrap(), "who: b\nx: 1\n");
+}
+
+#[tokio::test]
+async fn failed_plan_rolls_back_saved_vehicles() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    let path = home.path().join("report.json");
+    // car-1 saves, car-2 fails
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Delay(Duration::ZERO),
+    );
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Code(500, "disk full".to_string()),
+    );
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "--output",
+            "json",
+            "--output-file",
+            path.to_str().unwrap(),
+            "set",
+            "--all",
+            "a.b=4",
+            "--plan",
+            "--yes",
+            "--rollback",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::Server { code: 500, .. })
+    ));
+    let saves = server.saves();
+    assert_eq!(saves.len(), 2);
+    assert_eq!(saves[1].new_config, YAML);
+    assert_eq!(server.template(id).unwrap(), YAML);
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(report["vehicles"][0]["status"], "rolledBack");
+    assert_eq!(report["vehicles"][1]["status"], "failed");
+}
+
+#[tokio::test]
+async fn rollback_restores_the_journaled_config() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, id) = server().await;
+    let quiet = ["--manual", "false", "--auto-publish", "false"];
+
+    let mut set = vec!["set", "-v", "car-1", "a.b=2"];
+    set.extend_from_slice(&quiet);
+    common::run(&server.url(), &set).await.unwrap();
+    assert_ne!(server.template(id).unwrap(), YAML);
+
+    let mut rollback = vec!["rollback", "-v", "car-1"];
+    rollback.extend_from_slice(&quiet);
+    common::run(&server.url(), &rollback).await.unwrap();
+    assert_eq!(server.template(id).unwrap(), YAML);
+
+    // the rollback is journaled too, but someone changed the vehicle since
+    server.set_template(id, "a:\n  b: 8\n");
+    common::run(&server.url(), &rollback).await.unwrap();
+    assert_eq!(server.template(id).unwrap(), "a:\n  b: 8\n");
+}
+
+#[tokio::test]
+async fn audit_logs_save_publish_and_push() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+
+    common::run(
+        &server.url(),
+        &[
+            "set",
+            "-v",
+            "car-1",
+            "a.b=2",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "true",
+            "--push-type",
+            "silent",
+        ],
+    )
+    .await
+    .unwrap();
+
+    let log = std::fs::read_to_string(home.path().join(".cache/ota-yaml/audit.jsonl")).unwrap();
+    let records: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<&str> = records
+        .iter()
+        .map(|r| r["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(events, ["save", "publish", "approve", "push"]);
+    for record in &records {
+        assert_eq!(record["vehicleId"], id);
+        assert_eq!(record["vehicleName"], "car-1");
+        assert_eq!(record["host"], server.url());
+    }
+    assert_eq!(
+        records[0]["changes"],
+        serde_json::json!([{"op": "mod", "path": "a.b", "value": 2}])
+    );
+    assert_eq!(records[0]["ok"], true);
+    assert_eq!(records[1]["configId"], records[3]["configId"]);
+    assert_eq!(records[3]["pushType"], "UPGRADE_SELIENT");
+
+    // querying needs no server
+    common::run(
+        "ws://127.0.0.1:1",
+        &["audit", "-v", "car-1", "--since", "2000-01-01"],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn json_report_lists_every_vehicle() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    let path = home.path().join("report.json");
+
+    common::run(
+        &server.url(),
+        &[
+            "--output",
+            "json",
+            "--output-file",
+            path.to_str().unwrap(),
+            "set",
+            "--all",
+            "a.b=7",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "true",
+            "--push-type",
+            "none",
+        ],
+    )
+    .await
+    .unwrap();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let vehicles = report["vehicles"].as_array().unwrap();
+    assert_eq!(vehicles.len(), 2);
+    assert_eq!(vehicles[0]["vehicleId"], id);
+    assert_eq!(vehicles[0]["status"], "modified");
+    assert_eq!(vehicles[0]["changes"], 1);
+    assert_eq!(vehicles[0]["published"]["approved"], true);
+    assert_eq!(vehicles[0]["published"]["pushed"], serde_json::Value::Null);
+    assert_eq!(vehicles[1]["vehicleName"], "car-2");
+    assert_eq!(vehicles[1]["status"], "unchanged");
+    assert_eq!(vehicles[1]["changes"], 0);
+    // no useless config versions for the unchanged vehicle
+    assert_eq!(server.saves().len(), 1);
+    assert_eq!(server.publishes().len(), 1);
+}
+
+#[tokio::test]
+async fn broken_template_fails_without_a_terminal() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    server.set_template(id, "a: [1\n");
+    let path = home.path().join("report.json");
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "--output",
+            "json",
+            "--output-file",
+            path.to_str().unwrap(),
+            "--on-failure",
+            "continue",
+            "set",
+            "--all",
+            "a.b=8",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::InvalidYaml { .. })
+    ));
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let vehicle = |name| {
+        report["vehicles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|v| v["vehicleName"] == name)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(vehicle("car-1")["status"], "failed");
+    let error = vehicle("car-1")["error"].as_str().unwrap().to_string();
+    assert!(error.starts_with("invalid yaml at 2:1"), "{}", error);
+    assert_eq!(vehicle("car-2")["status"], "modified");
+}
+
+#[tokio::test]
+async fn set_through_a_scalar_fails() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "set",
+            "-v",
+            "car-1",
+            "a.b.x=1",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(e.to_string().contains("a.b is not a mapping"), "{}", e);
+    assert!(server.saves().is_empty());
+}
+
+#[tokio::test]
+async fn apply_twice_after_one_download() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    let dir = home.path().join("fleet");
+    let dir = dir.to_str().unwrap();
+    let file = home.path().join("fleet/car-1.yaml");
+    let apply = ["apply", dir, "--manual", "false", "--auto-publish", "false"];
+
+    common::run(
+        &server.url(),
+        &["get", "-v", "car-1", "-o", dir, "--metadata"],
+    )
+    .await
+    .unwrap();
+    for b in ["2", "3"] {
+        std::fs::write(&file, YAML.replace("b: 1", &format!("b: {}", b))).unwrap();
+        common::run(&server.url(), &apply).await.unwrap();
+    }
+    assert_eq!(server.saves().len(), 2);
+    assert!(server.template(id).unwrap().contains("b: 3"));
+}
+
+#[tokio::test]
+async fn apply_keeps_a_comment_only_change() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    let file = home.path().join("car-1.yaml");
+    let documented = format!("# documented\n{}", YAML);
+    std::fs::write(&file, &documented).unwrap();
+
+    common::run(
+        &server.url(),
+        &[
+            "apply",
+            file.to_str().unwrap(),
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(server.template(id).unwrap(), documented);
+}
+
+#[tokio::test]
+async fn refused_apply_fails_the_run() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    let dir = home.path().join("fleet");
+    let dir = dir.to_str().unwrap();
+
+    common::run(
+        &server.url(),
+        &["get", "-v", "car-1", "-o", dir, "--metadata"],
+    )
+    .await
+    .unwrap();
+    server.set_template(id, "a:\n  b: 9\n");
+    std::fs::write(home.path().join("fleet/car-1.yaml"), "a:\n  b: 2\n").unwrap();
+    let e = common::run(
+        &server.url(),
+        &[
+            "apply",
+            dir,
+            "--on-conflict",
+            "refuse",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(e.to_string().contains("refused"), "{}", e);
+    assert!(server.saves().is_empty());
+}
+
+const NAMED: &str = "mode: a\nitems:\n  - name: a\n    v: 1\n  - name: b\n    v: 2\n";
+
+/// download car-1 holding `NAMED`, let the server change to `server_side`,
+/// then apply `local` with `--on-conflict merge`.
+async fn merge(server_side: &str, local: &str) -> (MockServer, i32, anyhow::Result<()>) {
+    let home = home();
+    let (server, id) = server().await;
+    server.set_template(id, NAMED);
+    let dir = home.path().join("fleet");
+    let dir = dir.to_str().unwrap();
+    common::run(
+        &server.url(),
+        &["get", "-v", "car-1", "-o", dir, "--metadata"],
+    )
+    .await
+    .unwrap();
+    server.set_template(id, server_side);
+    std::fs::write(home.path().join("fleet/car-1.yaml"), local).unwrap();
+    let result = common::run(
+        &server.url(),
+        &[
+            "apply",
+            dir,
+            "--on-conflict",
+            "merge",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await;
+    (server, id, result)
+}
+
+#[tokio::test]
+async fn merge_replays_a_deleted_item_onto_the_server_copy() {
+    let _lock = LOCK.lock().await;
+    let (server, id, result) = merge(
+        &NAMED.replace("mode: a", "mode: b"),
+        "mode: a\nitems:\n  - name: a\n    v: 1\n",
+    )
+    .await;
+    result.unwrap();
+    assert_eq!(
+        server.template(id).unwrap(),
+        "mode: b\nitems:\n  - name: a\n    v: 1\n"
+    );
+}
+
+#[tokio::test]
+async fn merge_of_the_same_node_fails() {
+    let _lock = LOCK.lock().await;
+    let (server, _, result) = merge(
+        &NAMED.replace("v: 2", "v: 3"),
+        &NAMED.replace("v: 2", "v: 4"),
+    )
+    .await;
+    let e = result.unwrap_err();
+    assert!(e.to_string().contains("refused"), "{}", e);
+    assert!(server.saves().is_empty());
+}
+
+#[tokio::test]
+async fn noop_plan_saves_nothing() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+
+    // nothing to confirm, so no --yes is needed
+    common::run(
+        &server.url(),
+        &[
+            "set",
+            "-v",
+            "car-1",
+            "a.b=1",
+            "--plan",
+            "--auto-publish",
+            "true",
+            "--push-type",
+            "force",
+        ],
+    )
+    .await
+    .unwrap();
+
+    assert!(server.saves().is_empty());
+    assert!(server.publishes().is_empty());
+}
+
+#[tokio::test]
+async fn junit_report_marks_failed_and_unreached_vehicles() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, _) = server().await;
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Code(500, "disk <full>".to_string()),
+    );
+    let path = home.path().join("report.xml");
+
+    common::run(
+        &server.url(),
+        &[
+            "--output",
+            "junit",
+            "--output-file",
+            path.to_str().unwrap(),
+            "set",
+            "--all",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+
+    let xml = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        xml.contains(r#"tests="2" failures="1" skipped="1""#),
+        "{}",
+        xml
+    );
+    assert!(xml.contains("disk &lt;full&gt;"), "{}", xml);
+    assert!(
+        xml.contains(r#"<skipped message="not reached"/>"#),
+        "{}",
+        xml
+    );
+}
+
+#[tokio::test]
+async fn junit_report_fails_a_refused_approval() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, _) = server().await;
+    server.inject(
+        EventType::OtaEditConfigurePublish,
+        Fault::Refuse("not allowed".to_string()),
+    );
+    let path = home.path().join("report.xml");
+
+    common::run(
+        &server.url(),
+        &[
+            "--output",
+            "junit",
+            "--output-file",
+            path.to_str().unwrap(),
+            "set",
+            "-v",
+            "car-1",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "true",
+            "--push-type",
+            "none",
+        ],
+    )
+    .await
+    .unwrap();
+
+    let xml = std::fs::read_to_string(&path).unwrap();
+    assert!(xml.contains(r#"tests="1" failures="1""#), "{}", xml);
+    assert!(
+        xml.contains(r#"<failure message="approve refused"/>"#),
+        "{}",
+        xml
+    );
+}
+
+#[tokio::test]
+async fn refused_save_fails_without_publishing() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let (server, id) = server().await;
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Refuse("template locked".to_string()),
+    );
+    let path = home.path().join("report.json");
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "--output",
+            "json",
+            "--output-file",
+            path.to_str().unwrap(),
+            "set",
+            "--all",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "true",
+            "--push-type",
+            "force",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::SaveRejected(message)) if message == "template locked"
+    ));
+    assert!(server.publishes().is_empty());
+    assert_eq!(server.template(id).unwrap(), YAML);
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(report["vehicles"][0]["status"], "failed");
+    assert_eq!(
+        report["vehicles"][0]["error"],
+        "save rejected: template locked"
+    );
+    assert_eq!(report["vehicles"][1]["status"], "notReached");
+}
+
+#[tokio::test]
+async fn continue_policy_handles_the_remaining_vehicles() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, id) = server().await;
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Code(500, "disk full".to_string()),
+    );
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "--on-failure",
+            "continue",
+            "set",
+            "--all",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    // the run still fails, but car-2 was saved
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::Server { code: 500, .. })
+    ));
+    let saves = server.saves();
+    assert_eq!(saves.len(), 1);
+    assert_ne!(saves[0].vehicle_id, id);
+}
+
+#[tokio::test]
+async fn retries_recover_a_failed_save() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, id) = server().await;
+    for _ in 0..2 {
+        server.inject(
+            EventType::OtaAddVehicleTemplateItem,
+            Fault::Refuse("busy".to_string()),
+        );
+    }
+
+    common::run(
+        &server.url(),
+        &[
+            "--retries",
+            "2",
+            "set",
+            "-v",
+            "car-1",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(server.saves().len(), 1);
+    assert_eq!(
+        server.template(id).unwrap(),
+        "# car config\na:\n  b: 3 # keep me\n  c: [x, y]\n"
+    );
+}
+
+#[tokio::test]
+async fn timed_out_save_that_landed_is_not_retried() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let (server, _) = server().await;
+    server.inject(
+        EventType::OtaAddVehicleTemplateItem,
+        Fault::Delay(Duration::from_millis(1500)),
+    );
+
+    let e = common::run(
+        &server.url(),
+        &[
+            "--timeout",
+            "1",
+            "--retries",
+            "1",
+            "set",
+            "-v",
+            "car-1",
+            "a.b=3",
+            "--manual",
+            "false",
+            "--auto-publish",
+            "false",
+        ],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<OtaError>(),
+        Some(OtaError::Timeout { .. })
+    ));
+    assert_eq!(server.saves().len(), 1);
+}
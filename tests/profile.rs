@@ -0,0 +1,72 @@
+use common::{home, server, LOCK};
+use ota_yaml::EventType;
+
+mod common;
+
+fn write_config(home: &std::path::Path, text: &str) {
+    let dir = home.join(".config/ota-yaml");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("config.yaml"), text).unwrap();
+}
+
+#[tokio::test]
+async fn profiles_keep_hosts_and_tokens_apart() {
+    let _lock = LOCK.lock().await;
+    let home = home();
+    let ((prod, _), (staging, _)) = (server().await, server().await);
+    write_config(
+        home.path(),
+        &format!(
+            "default: prod\nprofiles:\n  prod:\n    endpoint: {}\n    username: admin\n    push_type: force\n  staging:\n    endpoint: {}\n    username: admin\n",
+            prod.url(),
+            staging.url()
+        ),
+    );
+
+    common::run_raw(&["--password", "secret", "login"])
+        .await
+        .unwrap();
+    common::run_raw(&["--profile", "staging", "--password", "secret", "login"])
+        .await
+        .unwrap();
+    // the prod token is never offered to staging
+    assert!(staging
+        .requests()
+        .iter()
+        .all(|e| *e != EventType::TokenLoginRequest));
+    let tokens = std::fs::read_dir(home.path().join(".cache/ota-yaml/tokens"))
+        .unwrap()
+        .count();
+    assert_eq!(tokens, 2);
+
+    // the cached prod token is reused, the profile supplies the push type
+    common::run_raw(&[
+        "set",
+        "-v",
+        "car-1",
+        "a.b=2",
+        "--manual",
+        "false",
+        "--auto-publish",
+        "true",
+    ])
+    .await
+    .unwrap();
+    let logins = prod
+        .requests()
+        .iter()
+        .filter(|e| **e == EventType::LoginRequest)
+        .count();
+    assert_eq!(logins, 1);
+    assert_eq!(
+        prod.publishes()[0].pushed.as_deref(),
+        Some("UPGRADE_ENFORCE")
+    );
+    assert!(staging.saves().is_empty());
+
+    common::run_raw(&["profiles", "list"]).await.unwrap();
+    let e = common::run_raw(&["--profile", "dev", "login"])
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("no profile dev"), "{}", e);
+}
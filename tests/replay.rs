@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use common::{home, LOCK, YAML};
+use ota_yaml::cassette::ReplayServer;
+use ota_yaml::mock::MockServer;
+
+mod common;
+
+const PUSH: &[&str] = &[
+    "set",
+    "-v",
+    "car-1",
+    "a.b=2",
+    "--manual",
+    "false",
+    "--auto-publish",
+    "true",
+    "--push-type",
+    "force",
+];
+
+#[tokio::test]
+async fn recorded_session_replays() {
+    let _lock = LOCK.lock().await;
+    let dir = tempfile::tempdir().unwrap();
+    let cassette = dir.path().join("session.jsonl");
+
+    let server = MockServer::start().await.unwrap();
+    server.add_user("admin", "secret");
+    server.add_vehicle("car-1", YAML);
+    {
+        let _home = home();
+        let record = cassette.to_str().unwrap();
+        let mut args = vec!["--record", record];
+        args.extend_from_slice(PUSH);
+        common::run(&server.url(), &args).await.unwrap();
+    }
+    drop(server);
+    let text = std::fs::read_to_string(&cassette).unwrap();
+    assert!(!text.contains("secret"));
+    assert!(!text.contains("token-"));
+
+    let _home = home();
+    // the published archive is named after the current time
+    let replay = ReplayServer::start(&cassette)
+        .await
+        .unwrap()
+        .ignore_field("key")
+        .ignore_field("name");
+    common::run(&replay.url(), PUSH).await.unwrap();
+    assert_eq!(replay.mismatches(), Vec::<String>::new());
+    assert_eq!(replay.remaining(), 0);
+}
+
+/// captured from a server refusing the approval, the push must be skipped.
+#[tokio::test]
+async fn unapproved_config_is_not_pushed() {
+    let _lock = LOCK.lock().await;
+    let _home = home();
+    let cassette =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cassettes/approve_refused.jsonl");
+    let replay = ReplayServer::start(&cassette)
+        .await
+        .unwrap()
+        .ignore_field("key")
+        .ignore_field("name");
+
+    common::run(&replay.url(), PUSH).await.unwrap();
+    assert_eq!(replay.mismatches(), Vec::<String>::new());
+    assert_eq!(replay.remaining(), 0);
+}